@@ -63,7 +63,7 @@ fn main() {
         GET_DROP_CARD_SUIT_OR_255,
         EQ,
         GET_GRAB_CARD_NUM_OR_255,
-        GET_GRAB_CARD_NUM_OR_255,
+        DUP,
         GET_DROP_CARD_NUM_OR_255,
         LITERAL,
         1,
@@ -82,7 +82,7 @@ fn main() {
         GET_DROP_CARD_SUIT_OR_255,
         NE,
         GET_GRAB_CARD_NUM_OR_255,
-        GET_GRAB_CARD_NUM_OR_255,
+        DUP,
         GET_DROP_CARD_NUM_OR_255,
         LITERAL,
         1,
@@ -1,5 +1,7 @@
 extern crate project_common;
+use project_common::vm::disasm::disassemble;
 use project_common::vm::instructions::*;
+use project_common::vm::verify::{stack_effect, verify};
 use project_common::vm::PrettyInstruction;
 use project_common::GameState;
 
@@ -10,7 +12,7 @@ use rand::{
 
 use std::cmp::{max, min};
 
-const INSTRUCTION_POOL: [u8; 51] = [
+const INSTRUCTION_POOL: [u8; 69] = [
     NO_OP,
     FILL_MOVE_TIMER,
     GRAB,
@@ -32,6 +34,15 @@ const INSTRUCTION_POOL: [u8; 51] = [
     LT_BRANCH,
     LE_BRANCH,
     JUMP,
+    IF_BACK,
+    EQ_BRANCH_BACK,
+    NE_BRANCH_BACK,
+    GT_BRANCH_BACK,
+    GE_BRANCH_BACK,
+    LT_BRANCH_BACK,
+    LE_BRANCH_BACK,
+    JUMP_BACK,
+    LOOP,
     EQ,
     NE,
     GT,
@@ -48,6 +59,11 @@ const INSTRUCTION_POOL: [u8; 51] = [
     SET_GRAB_DEPTH,
     LITERAL,
     FORGET,
+    DUP,
+    SWAP,
+    OVER,
+    ROT,
+    FORGET_N,
     CAN_GRAB,
     HANDLE_BUTTON_PRESS,
     //Leaving this out on purpose.
@@ -63,15 +79,20 @@ const INSTRUCTION_POOL: [u8; 51] = [
     MOVE_CARDS,
     GET_SELECT_DROP,
     GET_CELL_LEN,
+    GET_CELL_LEN_AT,
+    GET_TOP_CARD_AT_OR_255,
+    DISCARD_CARD_AT,
+    PUT_CARD_AT,
     HALT,
 ];
 
-const ZERO_PARAM_INSTRUCTION_POOL: [u8; 20] = [
+const ZERO_PARAM_INSTRUCTION_POOL: [u8; 21] = [
     NO_OP,
     FILL_MOVE_TIMER,
     GRAB,
     DROP,
     JUMP,
+    JUMP_BACK,
     GET_SELECT_POS,
     GET_SELECT_DEPTH,
     GET_GRAB_POS,
@@ -92,21 +113,29 @@ const ZERO_PARAM_INSTRUCTION_POOL: [u8; 20] = [
     //HALT,
 ];
 
-const ONE_PARAM_INSTRUCTION_POOL: [u8; 9] = [
+const ONE_PARAM_INSTRUCTION_POOL: [u8; 16] = [
     NOT,
     IF,
+    IF_BACK,
+    LOOP,
     FORGET,
+    DUP,
+    FORGET_N,
     SET_SELECT_POS,
     SET_SELECT_DEPTH,
     SET_GRAB_POS,
     SET_GRAB_DEPTH,
     GET_CARD_NUM,
     GET_CARD_SUIT,
+    GET_CELL_LEN_AT,
+    GET_TOP_CARD_AT_OR_255,
+    DISCARD_CARD_AT,
 ];
 
-const TWO_PARAM_INSTRUCTION_POOL: [u8; 20] = [
+const TWO_PARAM_INSTRUCTION_POOL: [u8; 29] = [
     ADD, SUB, MUL, DIV, MAX, MIN, AND, OR, EQ_BRANCH, NE_BRANCH, GT_BRANCH, GE_BRANCH, LT_BRANCH,
-    LE_BRANCH, EQ, NE, GT, GE, LT, LE,
+    LE_BRANCH, EQ_BRANCH_BACK, NE_BRANCH_BACK, GT_BRANCH_BACK, GE_BRANCH_BACK, LT_BRANCH_BACK,
+    LE_BRANCH_BACK, EQ, NE, GT, GE, LT, LE, SWAP, OVER, PUT_CARD_AT,
 ];
 
 #[allow(dead_code)]
@@ -148,10 +177,10 @@ const GRAB_ZERO_PARAM_INSTRUCTION_POOL: [u8; 5] = [
     GET_SELECT_DROP,
 ];
 
-const GRAB_ONE_PARAM_INSTRUCTION_POOL: [u8; 4] = [NOT, FORGET, GET_CARD_NUM, GET_CARD_SUIT];
+const GRAB_ONE_PARAM_INSTRUCTION_POOL: [u8; 5] = [NOT, FORGET, DUP, GET_CARD_NUM, GET_CARD_SUIT];
 
-const GRAB_TWO_PARAM_INSTRUCTION_POOL: [u8; 14] = [
-    ADD, SUB, MUL, DIV, MAX, MIN, AND, OR, EQ, NE, GT, GE, LT, LE,
+const GRAB_TWO_PARAM_INSTRUCTION_POOL: [u8; 16] = [
+    ADD, SUB, MUL, DIV, MAX, MIN, AND, OR, EQ, NE, GT, GE, LT, LE, SWAP, OVER,
 ];
 
 fn generate_A_button_instruction_compatible_with_stack_depth<R: Rng>(
@@ -190,6 +219,11 @@ fn generate_A_button_instruction_compatible_with_stack_depth<R: Rng>(
     }
 }
 
+// The most `FORGET_N` is given to forget. Something may jump into the count and run it as an
+// instruction, and counts up to this one are `NO_OP`, `FILL_MOVE_TIMER` and `GRAB`, which are
+// harmless to run.
+const MAX_FORGET_COUNT: u8 = 2;
+
 fn insert_instruction<R: Rng>(
     rng: &mut R,
     output: &mut Vec<u8>,
@@ -201,8 +235,9 @@ fn insert_instruction<R: Rng>(
     let len = output.len();
 
     match instruction {
-        NOT | NO_OP | GET_CARD_SUIT | GET_CARD_NUM | GRAB | DROP | HALT | FILL_MOVE_TIMER
-        | JUMP | HANDLE_BUTTON_PRESS => {
+        NOT | NO_OP | GET_CARD_SUIT | GET_CARD_NUM | GET_CELL_LEN_AT | GET_TOP_CARD_AT_OR_255
+        | GRAB | DROP | HALT | FILL_MOVE_TIMER | JUMP | JUMP_BACK | LOOP | SWAP | ROT | FORGET_N
+        | HANDLE_BUTTON_PRESS => {
             // *stack_depth += 0;
         }
         GET_SELECT_POS
@@ -218,17 +253,22 @@ fn insert_instruction<R: Rng>(
         | GET_GRAB_CARD_NUM_OR_255
         | GET_GRAB_CARD_SUIT_OR_255
         | GET_DROP_CARD_NUM_OR_255
-        | GET_DROP_CARD_SUIT_OR_255 => {
+        | GET_DROP_CARD_SUIT_OR_255
+        | DUP
+        | OVER => {
             let previous_instruction = output.last().cloned().unwrap_or(NO_OP);
             if previous_instruction != LITERAL {
                 *stack_depth += 1;
             }
         }
-        IF | FORGET | SET_SELECT_POS | SET_SELECT_DEPTH | SET_GRAB_POS | SET_GRAB_DEPTH => {
+        IF | IF_BACK | FORGET | SET_SELECT_POS | SET_SELECT_DEPTH | SET_GRAB_POS
+        | SET_GRAB_DEPTH | DISCARD_CARD_AT => {
             *stack_depth -= 1;
         }
         ADD | SUB | MUL | DIV | MAX | MIN | AND | OR | EQ_BRANCH | NE_BRANCH | GT_BRANCH
-        | GE_BRANCH | LT_BRANCH | LE_BRANCH | EQ | NE | GT | GE | LT | LE => {
+        | GE_BRANCH | LT_BRANCH | LE_BRANCH | EQ_BRANCH_BACK | NE_BRANCH_BACK | GT_BRANCH_BACK
+        | GE_BRANCH_BACK | LT_BRANCH_BACK | LE_BRANCH_BACK | EQ | NE | GT | GE | LT | LE
+        | PUT_CARD_AT => {
             *stack_depth -= 2;
         }
         MOVE_CARDS => {
@@ -274,6 +314,67 @@ fn insert_instruction<R: Rng>(
 
             add_restriction(restrictions, absolute_target, *stack_depth);
         }
+        FORGET_N => {
+            let forget_count = rng.gen_range(0, min(*stack_depth, MAX_FORGET_COUNT) + 1);
+            *stack_depth -= forget_count;
+
+            output.push(instruction);
+            if len + 1 < count {
+                *stack_depth = min(*stack_depth, restrictions[len + 1]);
+            }
+            output.push(forget_count);
+        }
+        IF_BACK | EQ_BRANCH_BACK | NE_BRANCH_BACK | GT_BRANCH_BACK | GE_BRANCH_BACK
+        | LT_BRANCH_BACK | LE_BRANCH_BACK | JUMP_BACK | LOOP => {
+            // Only jump back over code the verifier is completely happy with, which means every
+            // trip around the loop leaves the stack as deep as it found it. That code cannot
+            // change after this, since forward branches only ever target later instructions. As
+            // with the forward branches, the offset must also be harmless to execute, in case
+            // something jumps into it.
+            // A `NO_OP` stands in for the branch, so the depth it would see gets recorded.
+            let mut probe = output.clone();
+            probe.push(NO_OP);
+            let report = verify(&probe);
+
+            // After the branch has popped its condition, if it is reachable at all.
+            let depth_after_branch = report.depths[len].map(|depth| {
+                let (pops, _) = stack_effect(instruction).unwrap_or((0, 0));
+                depth.checked_sub(pops)
+            });
+            let first_clean_target = report
+                .violations
+                .last()
+                .map_or(0, |violation| violation.index + 1);
+
+            let offsets: Vec<u8> = (max(len.saturating_sub(253), first_clean_target)..len)
+                .filter(|&target| {
+                    let offset = (len + 2 - target) as u8;
+                    if offset == JUMP
+                        || offset == JUMP_BACK
+                        || !ZERO_PARAM_INSTRUCTION_POOL.contains(&offset)
+                    {
+                        return false;
+                    }
+
+                    match depth_after_branch {
+                        Some(Some(depth)) => report.depths[target] == Some(depth),
+                        Some(None) => false,
+                        None => true,
+                    }
+                })
+                .map(|target| (len + 2 - target) as u8)
+                .collect();
+
+            if let Some(&offset) = rng.choose(&offsets) {
+                output.push(instruction);
+                if len + 1 < count {
+                    *stack_depth = min(*stack_depth, restrictions[len + 1]);
+                }
+                output.push(offset);
+            } else {
+                output.push(NO_OP);
+            }
+        }
         _ => {
             output.push(instruction);
         }
@@ -465,8 +566,11 @@ extern crate quickcheck;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use project_common::vm::{Outcome, VmConfig, VmError};
 
     const TEST_GENERATION_COUNT: usize = 100;
+    // Generated programs can loop, and every step is logged, so the default budget is too large.
+    const TEST_CONFIG: VmConfig = VmConfig { budget: 1024 };
 
     use quickcheck::{Arbitrary, Gen};
 
@@ -478,21 +582,30 @@ mod tests {
     fn jump_at_the_end_does_not_crash() {
         let mut game_state = GameState::new([0; 16], None);
 
-        game_state.interpret(&[JUMP]);
+        assert_eq!(
+            game_state.interpret(&[JUMP]),
+            Err(VmError::MissingOperand { ip: 0 })
+        );
     }
 
     #[test]
     fn jump_past_the_end_does_not_crash() {
         let mut game_state = GameState::new([0; 16], None);
 
-        game_state.interpret(&[JUMP, 255]);
+        assert_eq!(
+            game_state.interpret(&[JUMP, 255]),
+            Ok(Outcome::Finished { steps: 1 })
+        );
     }
 
     #[test]
     fn dissect() {
         let mut game_state = GameState::new([0; 16], Some(logger));
 
-        game_state.interpret(&[LITERAL, GET_SELECT_DEPTH]);
+        assert_eq!(
+            game_state.interpret(&[LITERAL, GET_SELECT_DEPTH]),
+            Ok(Outcome::Finished { steps: 1 })
+        );
     }
 
     #[test]
@@ -585,12 +698,13 @@ mod tests {
 
             let instructions = generate_grab(&mut rng, TEST_GENERATION_COUNT);
             for _ in 0..8 {
-                game_state.interpret(&instructions);
+                if over_or_underflowed(game_state.interpret_with_config(&instructions, &TEST_CONFIG)) {
+                    return false;
+                }
 
                 game_state.vm.clear();
             }
 
-            //didn't panic
             true
         }
 
@@ -603,12 +717,13 @@ mod tests {
 
             let instructions = generate(&mut rng, TEST_GENERATION_COUNT);
             for _ in 0..8 {
-                game_state.interpret(&instructions);
+                if over_or_underflowed(game_state.interpret_with_config(&instructions, &TEST_CONFIG)) {
+                    return false;
+                }
 
                 game_state.vm.clear();
             }
 
-            //didn't panic
             true
         }
 
@@ -743,6 +858,13 @@ mod tests {
         }
     }
 
+    fn over_or_underflowed(result: Result<Outcome, VmError>) -> bool {
+        matches!(
+            result,
+            Err(VmError::StackUnderflow { .. }) | Err(VmError::StackOverflow { .. })
+        )
+    }
+
     // https://stackoverflow.com/a/35907071/4496839
     // O(mn)
     // if that's too slow theres a KMP impl at
@@ -826,6 +948,14 @@ mod tests {
                         movetimer: game_state.movetimer,
                         vm: game_state.vm.clone(),
                         rng: game_state.rng.clone(),
+                        ruleset: game_state.ruleset.clone(),
+                        deck: game_state.deck.clone(),
+                        dealing: game_state.dealing,
+                        history: game_state.history.clone(),
+                        hint: game_state.hint,
+                        hint_budget: game_state.hint_budget,
+                        deal_search: game_state.deal_search,
+                        enabled_buttons: game_state.enabled_buttons.clone(),
                     })
                 },
             ))
@@ -875,17 +1005,5 @@ fn main() {
 }
 
 fn print_instructions(instructions: &Vec<u8>) {
-    let mut output = String::with_capacity(instructions.len() * 16);
-
-    output.push('[');
-    output.push('\n');
-
-    for &instruction in instructions.iter() {
-        output.push_str(&format!("    {},\n", PrettyInstruction(instruction)));
-    }
-
-    output.push(']');
-    output.push('\n');
-
-    println!("{}", output);
+    println!("{}", disassemble(instructions));
 }
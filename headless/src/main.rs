@@ -0,0 +1,394 @@
+// Runs the game without a browser: deals a game, feeds it the frames of a replay and/or a script
+// through `update_and_render`, and prints where the game ended up.
+
+extern crate project_common;
+use project_common::*;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+const USAGE: &str = "usage: headless [options]
+
+    --seed HEX           deal the game with this 32 digit hex seed
+    --deal N             deal this game from the deal catalogue (the default is 0)
+    --replay FILE        play this replay first, starting from its seed
+    --script FILE        then play this input script, or stdin if FILE is -
+    --solve              then play the moves the solver finds to win from there
+    --hint               then ask for a hint, and show it on the last frame
+    --save-replay FILE   save everything that was played as a replay
+    --framebuffer FILE   save the last frame as raw 128x128 RGBA pixels
+    --png FILE           save the last frame as a PNG
+    --ppm FILE           save the last frame as a PPM
+    --gif FILE           save every frame as an animated GIF
+    --scale N            make each pixel of the images N pixels wide and high (the default is 1)";
+
+// So that the end can be seen before the GIF starts again.
+const GIF_END_FRAMES: usize = 120;
+
+#[derive(Debug, Default)]
+struct Options {
+    seed: Option<[u8; 16]>,
+    deal: Option<usize>,
+    replay: Option<String>,
+    script: Option<String>,
+    solve: bool,
+    hint: bool,
+    save_replay: Option<String>,
+    framebuffer: Option<String>,
+    png: Option<String>,
+    ppm: Option<String>,
+    gif: Option<String>,
+    scale: Option<usize>,
+}
+
+fn parse_options<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--seed" => options.seed = Some(parse_seed(&value()?)?),
+            "--deal" => {
+                let deal = value()?;
+                options.deal = Some(
+                    deal.parse()
+                        .map_err(|_| format!("bad deal number `{}`", deal))?,
+                );
+            }
+            "--replay" => options.replay = Some(value()?),
+            "--script" => options.script = Some(value()?),
+            "--solve" => options.solve = true,
+            "--hint" => options.hint = true,
+            "--save-replay" => options.save_replay = Some(value()?),
+            "--framebuffer" => options.framebuffer = Some(value()?),
+            "--png" => options.png = Some(value()?),
+            "--ppm" => options.ppm = Some(value()?),
+            "--gif" => options.gif = Some(value()?),
+            "--scale" => {
+                let scale = value()?;
+                options.scale = match scale.parse() {
+                    Ok(scale) if scale > 0 => Some(scale),
+                    _ => return Err(format!("bad scale `{}`", scale)),
+                };
+            }
+            "--help" | "-h" => return Err(USAGE.to_owned()),
+            _ => return Err(format!("unknown option `{}`\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let starts = [
+        options.seed.is_some(),
+        options.deal.is_some(),
+        options.replay.is_some(),
+    ];
+    if starts.iter().filter(|&&start| start).count() > 1 {
+        return Err("only one of --seed, --deal and --replay can be given".to_owned());
+    }
+
+    Ok(options)
+}
+
+fn parse_seed(hex: &str) -> Result<[u8; 16], String> {
+    let bad_seed = || format!("`{}` is not a 32 digit hex seed", hex);
+
+    if hex.len() != 32 || !hex.is_ascii() {
+        return Err(bad_seed());
+    }
+
+    let mut seed = [0; 16];
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| bad_seed())?;
+    }
+
+    Ok(seed)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+
+    let result = if path == "-" {
+        io::stdin().read_to_end(&mut bytes).map(|_| ())
+    } else {
+        fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes).map(|_| ()))
+    };
+
+    result
+        .map(|_| bytes)
+        .map_err(|error| format!("{}: {}", path, error))
+}
+
+fn write_file(path: &str, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|error| format!("{}: {}", path, error))
+}
+
+// Deals the game, and puts everything there is to play into one replay.
+fn make_replay(options: &Options) -> Result<Replay, String> {
+    let mut replay = match options.replay {
+        Some(ref path) => {
+            Replay::from_bytes(&read_file(path)?).map_err(|error| format!("{}: {}", path, error))?
+        }
+        None => match options.seed {
+            Some(seed) => Replay::new(seed),
+            None => {
+                let deal = options.deal.unwrap_or(0);
+                let seed = dealing::catalogue_seed(deal)
+                    .ok_or_else(|| format!("there is no deal {} in the catalogue", deal))?;
+
+                Replay::with_dealing(seed, Dealing::Catalogue(deal))
+            }
+        },
+    };
+
+    if let Some(ref path) = options.script {
+        let script =
+            String::from_utf8(read_file(path)?).map_err(|_| format!("{}: not UTF-8", path))?;
+        let frames = parse_script(&script).map_err(|error| format!("{}: {}", path, error))?;
+
+        for gamepad in frames {
+            replay.record(gamepad);
+        }
+    }
+
+    if options.solve {
+        let state = replay.play(|state, input| state.update(input));
+
+        let moves = match solve(state.ruleset.layout, &state.cells, Budget::default()) {
+            Solution::Solved(moves) => moves,
+            Solution::Unsolvable => return Err("the game can't be won from here".to_owned()),
+            Solution::Unknown => return Err("the solver gave up before winning".to_owned()),
+        };
+        let frames = script_for_moves(&state, &moves)
+            .map_err(|move_| format!("couldn't press the buttons to make {:?}", move_))?;
+
+        for gamepad in frames {
+            replay.record(gamepad);
+        }
+    }
+
+    Ok(replay)
+}
+
+// Plays everything there is to play, calling `on_frame` with each frame that is drawn.
+fn run<F>(options: &Options, mut on_frame: F) -> Result<(GameState, Framebuffer, Replay), String>
+where
+    F: FnMut(&Framebuffer),
+{
+    let replay = make_replay(options)?;
+    let mut framebuffer = Framebuffer::new();
+
+    let mut state = replay.play(|state, input| {
+        update_and_render(&mut framebuffer, state, input);
+        on_frame(&framebuffer);
+    });
+
+    if options.hint {
+        state.request_hint();
+    }
+
+    // Even with no frames to play there should be something to look at, and a hint only shows up
+    // on a frame drawn after it.
+    if options.hint || replay.frame_count() == 0 {
+        draw(&mut framebuffer, &state);
+        on_frame(&framebuffer);
+    }
+
+    Ok((state, framebuffer, replay))
+}
+
+fn describe(state: &mut GameState, replay: &Replay) -> String {
+    let layout = state.ruleset.layout;
+    let mut lines = Vec::new();
+
+    lines.push(format!("seed: {}", hex(&replay.seed)));
+    lines.push(format!("frames: {}", replay.frame_count()));
+    lines.push(format!("wins: {}", state.wins));
+    lines.push(format!("won: {}", state.has_won()));
+    lines.push(format!(
+        "moves: {} ({} undone)",
+        state.history.moves().len(),
+        state.history.undone().len()
+    ));
+    lines.push(format!(
+        "selected: {}, depth {}",
+        cell_name(layout, state.selectpos),
+        state.selectdepth
+    ));
+    if state.selectdrop {
+        lines.push(format!(
+            "grabbed: {}, depth {}",
+            cell_name(layout, state.grabpos),
+            state.grabdepth
+        ));
+    }
+    if let Some(hint) = state.hint {
+        lines.push(format!("hint: {:?}", hint));
+    }
+
+    for pos in 0..=layout.cells_max_index() {
+        if pos == layout.button_column() {
+            continue;
+        }
+
+        let cards: Vec<String> = state.cells[pos as usize]
+            .iter()
+            .map(|&card| card_text(card))
+            .collect();
+
+        let line = format!("{:>12}: {}", cell_name(layout, pos), cards.join(" "));
+        lines.push(line.trim_end().to_owned());
+    }
+
+    lines.join("\n") + "\n"
+}
+
+// The framebuffer's pixels are stored as ABGR, so in little endian order their bytes are RGBA.
+fn rgba_bytes(framebuffer: &Framebuffer) -> Vec<u8> {
+    framebuffer
+        .buffer
+        .iter()
+        .flat_map(|&pixel| (0..4).map(move |i| (pixel >> (i * 8)) as u8))
+        .collect()
+}
+
+fn main() {
+    let result = parse_options(env::args().skip(1)).and_then(|options| {
+        let scale = options.scale.unwrap_or(1);
+        let mut gif = options.gif.as_ref().map(|_| GifEncoder::new(scale));
+
+        let (mut state, framebuffer, replay) = run(&options, |framebuffer| {
+            if let Some(ref mut gif) = gif {
+                gif.add_frame(framebuffer);
+            }
+        })?;
+
+        print!("{}", describe(&mut state, &replay));
+
+        if let Some(ref path) = options.save_replay {
+            write_file(path, &replay.to_bytes())?;
+        }
+        if let Some(ref path) = options.framebuffer {
+            write_file(path, &rgba_bytes(&framebuffer))?;
+        }
+
+        if let Some(ref path) = options.png {
+            write_file(path, &encode_png(&framebuffer, scale))?;
+        }
+        if let Some(ref path) = options.ppm {
+            write_file(path, &encode_ppm(&framebuffer, scale))?;
+        }
+        if let (Some(path), Some(mut gif)) = (options.gif.as_ref(), gif) {
+            for _ in 0..GIF_END_FRAMES {
+                gif.add_frame(&framebuffer);
+            }
+            write_file(path, &gif.finish())?;
+        }
+
+        Ok(())
+    });
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_options(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn seeds_are_read_as_hex() {
+        let options = parse(&["--seed", "000102030405060708090a0b0c0d0e0f"]).unwrap();
+        assert_eq!(
+            options.seed,
+            Some([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15])
+        );
+
+        assert!(parse_seed("0001").is_err());
+        assert!(parse_seed("zz0102030405060708090a0b0c0d0e0f").is_err());
+        assert!(parse(&["--seed", &"0".repeat(32), "--deal", "1"]).is_err());
+        assert!(parse(&["--deal"]).is_err());
+    }
+
+    #[test]
+    fn a_script_plays_the_same_as_the_game_would() {
+        let dir = env::temp_dir().join(format!("headless-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script_path = dir.join("script.txt");
+        let replay_path = dir.join("replay.szrp");
+
+        // Move the top card of the first column to a free cell.
+        let script = "wait 60\nA\nwait 4\nUp\nwait 4\nA\nwait 60\n";
+        fs::write(&script_path, script).unwrap();
+
+        let mut options = parse(&["--deal", "3"]).unwrap();
+        options.script = Some(script_path.to_string_lossy().into_owned());
+        options.save_replay = Some(replay_path.to_string_lossy().into_owned());
+
+        let (state, framebuffer, replay) = run(&options, |_| {}).unwrap();
+        write_file(options.save_replay.as_ref().unwrap(), &replay.to_bytes()).unwrap();
+
+        let mut expected = GameState::new(dealing::catalogue_seed(3).unwrap(), None);
+        let mut input = Input::new();
+        for gamepad in parse_script(script).unwrap() {
+            input.previous_gamepad = input.gamepad;
+            input.gamepad = gamepad;
+            expected.update(input);
+        }
+        assert_eq!(state.cells, expected.cells);
+        assert!(state
+            .history
+            .moves()
+            .iter()
+            .any(|entry| entry.kind == MoveKind::Player));
+
+        let mut drawn = Framebuffer::new();
+        draw(&mut drawn, &expected);
+        assert!(framebuffer == drawn);
+        assert_eq!(rgba_bytes(&framebuffer).len(), SCREEN_LENGTH * 4);
+
+        // Playing the saved replay ends up in the same place.
+        let replayed = run(
+            &Options {
+                replay: options.save_replay.clone(),
+                ..Options::default()
+            },
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(replayed.0.cells, state.cells);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_hint_is_asked_for_at_the_end() {
+        let options = parse(&["--deal", "0", "--hint"]).unwrap();
+
+        let (mut state, _, replay) = run(&options, |_| {}).unwrap();
+        assert!(state.hint.is_some());
+        assert!(describe(&mut state, &replay).contains("hint: "));
+    }
+
+    #[test]
+    fn solving_plays_on_to_a_win() {
+        let options = parse(&["--deal", "1", "--solve"]).unwrap();
+
+        let mut frames = 0;
+        let (mut state, _, replay) = run(&options, |_| frames += 1).unwrap();
+
+        assert!(state.has_won());
+        assert_eq!(frames, replay.frame_count());
+    }
+}
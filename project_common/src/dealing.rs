@@ -0,0 +1,224 @@
+// The deal catalogue is a numbered list of deals that are known to be winnable, so that people can
+// play the same deal and compare how they did. Each catalogue deal is the first of a series of
+// candidate seeds for its number that the solver won within `CATALOGUE_BUDGET`. Which candidate
+// that was is stored, rather than searched for each time, so the catalogue stays the same if the
+// solver changes.
+
+use std::sync::Arc;
+
+use solver::{self, Budget, Solution};
+use {DeckSpec, GameState, Ruleset};
+
+/// How `GameState::reset` picks the next deal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dealing {
+    /// Any deal, whether or not it can be won.
+    #[default]
+    Random,
+    /// Random deals, skipping the ones the solver can't win within the budget. Deals it runs out
+    /// of budget on are skipped too, so a small budget leaves out harder deals.
+    Solvable(Budget),
+    /// The deal with this number in the catalogue. The deal after it is the next one, going back
+    /// to the first at the end of the catalogue.
+    Catalogue(usize),
+}
+
+/// How many random deals `Dealing::Solvable` tries before it settles for one it could not win.
+pub const SOLVABLE_DEAL_ATTEMPTS: usize = 100;
+
+/// The budget the catalogue deals were checked with.
+pub const CATALOGUE_BUDGET: Budget = Budget {
+    nodes: 200_000,
+    time: None,
+};
+
+/// For each number in the catalogue, which of its candidate seeds is the deal.
+const CATALOGUE_ATTEMPTS: [u8; 100] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0,
+];
+
+pub fn catalogue_len() -> usize {
+    CATALOGUE_ATTEMPTS.len()
+}
+
+/// The seed of the catalogue deal with this number, which is dealt with the default ruleset and
+/// deck.
+pub fn catalogue_seed(number: usize) -> Option<[u8; 16]> {
+    CATALOGUE_ATTEMPTS
+        .get(number)
+        .map(|&attempt| candidate_seed(number, attempt))
+}
+
+fn candidate_seed(number: usize, attempt: u8) -> [u8; 16] {
+    let mut seed = [0; 16];
+    seed[0] = attempt;
+    seed[1] = 0x5a;
+    for (i, byte) in (number as u64).to_le_bytes().iter().enumerate() {
+        seed[8 + i] = *byte;
+    }
+
+    seed
+}
+
+/// Whether the solver can win the deal `seed` gives, within `budget`.
+pub fn is_solvable(seed: [u8; 16], ruleset: Arc<Ruleset>, deck: DeckSpec, budget: Budget) -> bool {
+    let state = GameState::with_deck(seed, None, ruleset, deck);
+
+    match solver::solve(state.ruleset.layout, &state.cells, budget) {
+        Solution::Solved(_) => true,
+        Solution::Unsolvable | Solution::Unknown => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use {Button, Input};
+
+    fn can_win(state: &GameState, budget: Budget) -> bool {
+        match solver::solve(state.ruleset.layout, &state.cells, budget) {
+            Solution::Solved(_) => true,
+            Solution::Unsolvable | Solution::Unknown => false,
+        }
+    }
+
+    fn assert_catalogue_deal_is_winnable(number: usize) {
+        let state = GameState::from_catalogue(number, None).unwrap();
+        let again = GameState::from_catalogue(number, None).unwrap();
+
+        assert_eq!(state.cells, again.cells);
+        assert!(can_win(&state, CATALOGUE_BUDGET), "deal {}", number);
+    }
+
+    // Solving every deal is too slow for a debug build, so this only checks a few, including some
+    // that needed a later candidate. `every_catalogue_deal_is_winnable` checks them all.
+    #[test]
+    fn catalogue_deals_are_always_the_same_winnable_deals() {
+        assert_eq!(
+            catalogue_seed(35),
+            Some([1, 0x5a, 0, 0, 0, 0, 0, 0, 35, 0, 0, 0, 0, 0, 0, 0])
+        );
+        assert_eq!(catalogue_seed(catalogue_len()), None);
+
+        for &number in [0, 33, 35, catalogue_len() - 1].iter() {
+            assert_catalogue_deal_is_winnable(number);
+        }
+    }
+
+    // Run this with `cargo test --release -- --ignored` after changing the catalogue.
+    #[test]
+    #[ignore]
+    fn every_catalogue_deal_is_winnable() {
+        for number in 0..catalogue_len() {
+            assert_catalogue_deal_is_winnable(number);
+        }
+    }
+
+    #[test]
+    fn restarting_a_catalogue_deal_deals_the_next_one() {
+        let mut state = GameState::from_catalogue(catalogue_len() - 2, None).unwrap();
+
+        state.reset();
+        assert_eq!(state.dealing, Dealing::Catalogue(catalogue_len() - 1));
+        assert_eq!(
+            state.cells,
+            GameState::from_catalogue(catalogue_len() - 1, None)
+                .unwrap()
+                .cells
+        );
+
+        state.reset();
+        assert_eq!(state.dealing, Dealing::Catalogue(0));
+        assert_eq!(
+            state.cells,
+            GameState::from_catalogue(0, None).unwrap().cells
+        );
+    }
+
+    #[test]
+    fn solvable_dealing_only_deals_winnable_games() {
+        // Small enough that the solver gives up on some deals.
+        let budget = Budget {
+            nodes: 2_000,
+            time: None,
+        };
+
+        for seed in 0..4 {
+            let mut state = GameState::solvable([seed; 16], None, budget);
+
+            for _ in 0..3 {
+                assert!(can_win(&state, budget), "seed {}", seed);
+                state.reset();
+                assert_eq!(state.dealing, Dealing::Solvable(budget));
+            }
+        }
+    }
+
+    static GAVE_UP: AtomicBool = AtomicBool::new(false);
+
+    fn give_up_logger(message: &str) {
+        if message.starts_with("none of") {
+            GAVE_UP.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn update_looks_for_a_solvable_deal_one_frame_at_a_time() {
+        // Too small to win anything with, so every deal is turned down.
+        let budget = Budget {
+            nodes: 1,
+            time: None,
+        };
+        let mut state = GameState::new([0; 16], Some(give_up_logger));
+        state.dealing = Dealing::Solvable(budget);
+        for cell in state.cells.iter_mut() {
+            cell.clear();
+        }
+
+        state.update(Input::new());
+        assert_eq!(state.wins, 1);
+
+        state.update(Input {
+            gamepad: Button::Start,
+            previous_gamepad: Button::Ty::empty(),
+        });
+        for attempts in 1..SOLVABLE_DEAL_ATTEMPTS {
+            assert_eq!(state.deal_search, Some(attempts));
+            assert!(!GAVE_UP.load(Ordering::SeqCst));
+            state.update(Input::new());
+        }
+
+        assert_eq!(state.deal_search, None);
+        assert!(GAVE_UP.load(Ordering::SeqCst));
+        assert!(!state.has_won());
+        assert_eq!(state.wins, 1);
+        assert_eq!(state.dealing, Dealing::Solvable(budget));
+    }
+
+    // Finds which candidate each catalogue deal should be. This takes a long time, so run it with
+    // `cargo test --release -- --ignored --nocapture` and paste the output over `CATALOGUE_ATTEMPTS`.
+    #[test]
+    #[ignore]
+    fn find_catalogue_attempts() {
+        let attempts: Vec<u8> = (0..catalogue_len())
+            .map(|number| {
+                (0..=255)
+                    .find(|&attempt| {
+                        is_solvable(
+                            candidate_seed(number, attempt),
+                            Arc::new(Ruleset::default()),
+                            DeckSpec::default(),
+                            CATALOGUE_BUDGET,
+                        )
+                    })
+                    .expect("no solvable candidate")
+            })
+            .collect();
+
+        println!("{:?}", attempts);
+    }
+}
@@ -0,0 +1,210 @@
+use rand::Rng;
+
+use inner_common::*;
+use Layout;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DealPattern {
+    /// One card to each column in turn.
+    RoundRobin,
+    /// Fill each column before moving on to the next, splitting the deck as evenly as possible.
+    ColumnByColumn,
+}
+
+/// What goes into a deck and where it is dealt. The cards use the same encoding as `getsuit` and
+/// `getcardnum`, so there is only room for three suits of up to `MAX_SUIT_NUM` ranks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeckSpec {
+    /// Clamped to 3.
+    pub suits: u8,
+    /// Clamped to `MAX_SUIT_NUM`. Each suit gets the cards numbered from 1 up to this.
+    pub ranks: u8,
+    /// How many dragons each suit gets.
+    pub dragons: u8,
+    pub flowers: u8,
+    /// The cells the deck is dealt into. Indices are masked like the other cell indices are.
+    pub columns: Vec<u8>,
+    pub pattern: DealPattern,
+}
+
+impl Default for DeckSpec {
+    /// The Shenzhen Solitaire deck, dealt into the tableau.
+    fn default() -> Self {
+        DeckSpec::for_layout(Layout::default())
+    }
+}
+
+impl DeckSpec {
+    /// The Shenzhen Solitaire deck, dealt into the tableau of `layout`.
+    pub fn for_layout(layout: Layout) -> Self {
+        DeckSpec {
+            suits: 3,
+            ranks: MAX_SUIT_NUM,
+            dragons: 4,
+            flowers: 1,
+            columns: (layout.start_of_tableau()..=layout.cells_max_index()).collect(),
+            pattern: DealPattern::RoundRobin,
+        }
+    }
+
+    /// The cards in the deck, before shuffling.
+    pub fn cards(&self) -> Vec<u8> {
+        let suits = self.suits.min(3);
+        let ranks = self.ranks.min(MAX_SUIT_NUM);
+
+        let mut deck = Vec::with_capacity(
+            suits as usize * (ranks as usize + self.dragons as usize) + self.flowers as usize,
+        );
+
+        for rank in 1..=ranks {
+            for suit in 0..suits {
+                deck.push(suit * 10 + rank);
+            }
+        }
+
+        for _ in 0..self.dragons {
+            for suit in 0..suits {
+                deck.push(suit * 10);
+            }
+        }
+
+        deck.extend((0..self.flowers).map(|_| FLOWER_CARD));
+
+        deck
+    }
+
+    /// Shuffles the deck into `cells`. Nothing is dealt if there are no columns.
+    pub fn deal<R: Rng>(&self, rng: &mut R, cells: &mut Cells) {
+        if self.columns.is_empty() {
+            return;
+        }
+
+        let mut deck = self.cards();
+        let len = deck.len();
+        let column_count = self.columns.len();
+
+        let mut dealt = 0;
+        while !deck.is_empty() {
+            let index = rng.gen_range(0, deck.len());
+
+            let column = match self.pattern {
+                DealPattern::RoundRobin => dealt % column_count,
+                DealPattern::ColumnByColumn => dealt * column_count / len,
+            };
+            cells[self.columns[column] as usize & 15].push(deck.swap_remove(index));
+
+            dealt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, XorShiftRng};
+
+    // How `GameState::new` dealt before there was a `DeckSpec`.
+    fn original_deal(seed: [u8; 16]) -> Cells {
+        let mut cells: Cells = Default::default();
+
+        let mut deck = Vec::new();
+
+        for i in 1..=MAX_SUIT_NUM {
+            deck.push(i);
+            deck.push(i + 10);
+            deck.push(i + 20);
+        }
+
+        for _ in 1..=4 {
+            deck.push(0);
+            deck.push(10);
+            deck.push(20);
+        }
+
+        deck.push(30);
+
+        let mut rng = XorShiftRng::from_seed(seed);
+
+        let mut deckpos = START_OF_TABLEAU;
+        while !deck.is_empty() {
+            let index = rng.gen_range(0, deck.len());
+            cells[deckpos as usize].push(deck.swap_remove(index));
+
+            deckpos = if deckpos >= CELLS_MAX_INDEX {
+                START_OF_TABLEAU
+            } else {
+                deckpos + 1
+            };
+        }
+
+        cells
+    }
+
+    #[test]
+    fn the_default_deck_deals_like_it_always_has() {
+        for i in 0..64 {
+            let seed = [i; 16];
+
+            let mut cells: Cells = Default::default();
+            DeckSpec::default().deal(&mut XorShiftRng::from_seed(seed), &mut cells);
+
+            assert_eq!(cells, original_deal(seed));
+        }
+    }
+
+    #[test]
+    fn small_decks_can_be_dealt_column_by_column() {
+        let spec = DeckSpec {
+            suits: 1,
+            ranks: 5,
+            dragons: 0,
+            flowers: 0,
+            columns: vec![8, 9, 10],
+            pattern: DealPattern::ColumnByColumn,
+        };
+        assert_eq!(spec.cards(), vec![1, 2, 3, 4, 5]);
+
+        let mut cells: Cells = Default::default();
+        spec.deal(&mut XorShiftRng::from_seed([1; 16]), &mut cells);
+
+        let lens: Vec<_> = cells.iter().map(|cell| cell.len()).collect();
+        assert_eq!(lens, vec![0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 1, 0, 0, 0, 0, 0]);
+
+        let mut dealt: Vec<_> = cells.iter().flat_map(|cell| cell.iter().cloned()).collect();
+        dealt.sort();
+        assert_eq!(dealt, spec.cards());
+    }
+
+    #[test]
+    fn games_keep_their_deck_when_reset() {
+        let spec = DeckSpec {
+            suits: 2,
+            ranks: 3,
+            dragons: 0,
+            flowers: 0,
+            ..DeckSpec::default()
+        };
+        let mut state = GameState::with_deck([0; 16], None, Default::default(), spec.clone());
+
+        state.reset();
+
+        assert_eq!(state.deck, spec);
+        let card_count: usize = state.cells.iter().map(|cell| cell.len()).sum();
+        assert_eq!(card_count, 6);
+    }
+
+    #[test]
+    fn specs_outside_the_card_encoding_are_clamped() {
+        let spec = DeckSpec {
+            suits: 5,
+            ranks: 12,
+            dragons: 1,
+            flowers: 2,
+            ..DeckSpec::default()
+        };
+
+        let cards = spec.cards();
+        assert_eq!(cards.len(), 3 * (MAX_SUIT_NUM as usize + 1) + 2);
+        assert!(cards.iter().all(|&card| card <= FLOWER_CARD));
+    }
+}
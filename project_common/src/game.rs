@@ -0,0 +1,374 @@
+use std::cmp::max;
+
+use inner_common::*;
+use {getcardnum, getsuit, Framebuffer, GameState, Input, Layout, Move};
+
+// The sprite and height of each button in the button column, from the bottom up.
+const BUTTON_SPRITES: [(u8, u8); SPECIAL_BUTTON_COUNT as usize] = [(56, 16), (57, 8), (58, 0)];
+
+/// Draws the game as it is, without updating it.
+pub fn draw(framebuffer: &mut Framebuffer, state: &GameState) {
+    framebuffer.clear();
+    framebuffer.draw_map();
+
+    framebuffer.print("wins:", 11, 122, 7);
+    framebuffer.print(&state.wins.to_string(), 35, 122, 7);
+
+    let layout = state.ruleset.layout;
+
+    for (&enabled, &(sprite, y)) in state.enabled_buttons.iter().zip(BUTTON_SPRITES.iter()) {
+        if enabled {
+            framebuffer.spr(sprite, layout.button_column() * 16, y);
+        }
+    }
+
+    for i in 0..=layout.cells_max_index() {
+        let (posx, posy) = get_card_pos(layout, i);
+
+        drawcell(framebuffer, &state.cells[i as usize], posx, posy);
+    }
+
+    if let Some(hint) = state.hint {
+        drawhint(framebuffer, layout, &state.cells, hint);
+    }
+
+    let selectpos = state.selectpos;
+    if state.selectdrop {
+        drawselect(
+            framebuffer,
+            layout,
+            &state.cells,
+            state.grabpos,
+            state.grabdepth as i8,
+            false,
+        );
+        if selectpos == layout.button_column() {
+            drawselectbutton(framebuffer, layout, state.selectdepth, state.selectdrop);
+        } else if selectpos <= layout.start_of_tableau() {
+            drawselect(
+                framebuffer,
+                layout,
+                &state.cells,
+                selectpos,
+                state.selectdepth as i8,
+                true,
+            );
+        } else {
+            drawselect(
+                framebuffer,
+                layout,
+                &state.cells,
+                selectpos,
+                -(state.grabdepth as i8) - 1,
+                true,
+            );
+        }
+    } else if selectpos == layout.button_column() {
+        drawselectbutton(framebuffer, layout, state.selectdepth, state.selectdrop);
+    } else {
+        drawselect(
+            framebuffer,
+            layout,
+            &state.cells,
+            selectpos,
+            state.selectdepth as i8,
+            false,
+        );
+    }
+}
+
+fn drawcard(framebuffer: &mut Framebuffer, cardnum: u8, posx: u8, posy: u8) {
+    if cardnum == CARD_BACK {
+        framebuffer.sspr(0, 32, 16, 24, posx, posy);
+        return;
+    }
+
+    framebuffer.sspr(0, 8, 16, 24, posx, posy);
+
+    let suit = getsuit(cardnum);
+    let num = getcardnum(cardnum);
+
+    if num == 0 {
+        let sprite = if suit == 1 {
+            23
+        } else if suit == 2 {
+            39
+        } else if suit == 3 {
+            55
+        } else {
+            7
+        };
+
+        framebuffer.spr(sprite, posx, posy);
+        framebuffer.spr_flip_both(sprite, posx + 8, posy + 16);
+    } else {
+        let (suitcolor, sprite) = if suit == 1 {
+            (3, 22)
+        } else if suit == 2 {
+            (0, 38)
+        } else {
+            (8, 6)
+        };
+
+        framebuffer.print(&num.to_string(), posx + 3, posy + 3, suitcolor);
+        framebuffer.spr(sprite, posx + 4, posy + 8);
+        framebuffer.print(&num.to_string(), posx + 10, posy + 16, suitcolor);
+    }
+}
+
+fn drawcell(framebuffer: &mut Framebuffer, cell: &[u8], posx: u8, posy: u8) {
+    for (i, &card) in cell.iter().enumerate() {
+        drawcard(framebuffer, card, posx, posy + (i as u8 * 8))
+    }
+}
+
+fn drawselect(
+    framebuffer: &mut Framebuffer,
+    layout: Layout,
+    cells: &Cells,
+    pos: u8,
+    depth: i8,
+    drop: bool,
+) {
+    let spritex = if drop { 32 } else { 16 };
+    let spritey = 32;
+
+    let (posx, mut posy) = get_card_pos(layout, pos);
+
+    let len = cells[pos as usize].len() as u8;
+    if len > 0 {
+        posy = (posy as i8 + ((len as i8 - max(depth, -1) - 1) * 8)) as u8;
+    }
+
+    framebuffer.sspr(spritex, spritey, 16, 8, posx, posy);
+
+    let truedepth = if depth < 0 { i8::abs(depth) - 1 } else { depth };
+    for _ in 0..=truedepth {
+        posy += 8;
+        framebuffer.sspr(spritex, spritey + 8, 16, 8, posx, posy);
+    }
+    posy += 8;
+    framebuffer.sspr(spritex, spritey + 16, 16, 8, posx, posy);
+}
+
+fn drawselectbutton(framebuffer: &mut Framebuffer, layout: Layout, depth: u8, drop: bool) {
+    let sprite = if drop { 71 } else { 70 };
+
+    framebuffer.spr(sprite, layout.button_column() * 16, 16 - (depth * 8));
+}
+
+// Marks the cards to grab the way a selection is, and where to drop them the way a drop is.
+fn drawhint(framebuffer: &mut Framebuffer, layout: Layout, cells: &Cells, hint: Move) {
+    match hint {
+        Move::Cards {
+            grabpos,
+            depth,
+            droppos,
+        } => {
+            drawselect(framebuffer, layout, cells, grabpos, depth as i8, false);
+
+            let dropdepth = if layout.is_tableau(droppos) {
+                -(depth as i8) - 1
+            } else {
+                0
+            };
+            drawselect(framebuffer, layout, cells, droppos, dropdepth, true);
+        }
+        Move::Dragons { suit } => drawselectbutton(framebuffer, layout, suit, false),
+    }
+}
+
+// The free cells and the button column are lined up from the left edge of the screen, with the
+// flower foundation just after the half width button column, and the other foundations are lined
+// up against the right edge.
+fn get_card_pos(layout: Layout, pos: u8) -> (u8, u8) {
+    if pos >= layout.start_of_tableau() {
+        ((pos - layout.start_of_tableau()) * 16, 24)
+    } else if pos >= layout.start_of_foundations() {
+        (
+            SCREEN_WIDTH as u8 - (layout.end_of_foundations() - pos + 1) * 16,
+            0,
+        )
+    } else if pos == layout.flower_foundation() {
+        (layout.button_column() * 16 + 8, 0)
+    } else {
+        (pos * 16, 0)
+    }
+}
+
+/// Does a frame of the game, which is all a frontend needs to do other than showing the framebuffer
+/// and keeping track of the input.
+#[inline]
+pub fn update_and_render(framebuffer: &mut Framebuffer, state: &mut GameState, input: Input) {
+    state.update(input);
+
+    draw(framebuffer, state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dealing;
+    use image::{decode_png, encode_png, rgb_bytes};
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use {draw_winning_screen, Ruleset};
+
+    // Compares what was drawn with `golden/<name>.png`. Run the tests with `UPDATE_GOLDEN` set to
+    // write the images instead, and look over the changes to them before committing.
+    fn assert_matches_golden(name: &str, framebuffer: &Framebuffer) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join(format!("{}.png", name));
+
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, encode_png(framebuffer, 1)).unwrap();
+            return;
+        }
+
+        let golden = fs::read(&path).unwrap_or_else(|error| {
+            panic!(
+                "{}: {}, so run with UPDATE_GOLDEN=1 to write it",
+                path.display(),
+                error
+            )
+        });
+        let (width, height, expected) =
+            decode_png(&golden).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        assert_eq!((width, height), (SCREEN_WIDTH, SCREEN_HEIGHT));
+
+        // The pixels that match are greyed out, so the ones that don't stand out in red.
+        let mut diff = Framebuffer::new();
+        let mut mismatches = 0;
+        let actual = rgb_bytes(framebuffer, 1);
+        for (i, (actual, expected)) in actual.chunks(3).zip(expected.chunks(3)).enumerate() {
+            diff.buffer[i] = if actual == expected {
+                let grey = (actual.iter().map(|&c| c as u32).sum::<u32>() / 9) & 0xff;
+                0xff00_0000 | grey << 16 | grey << 8 | grey
+            } else {
+                mismatches += 1;
+                0xff00_00ff
+            };
+        }
+
+        if mismatches > 0 {
+            let dir = env::temp_dir().join("golden-diffs");
+            let actual_path = dir.join(format!("{}.png", name));
+            let diff_path = dir.join(format!("{}.diff.png", name));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(&actual_path, encode_png(framebuffer, 1)).unwrap();
+            fs::write(&diff_path, encode_png(&diff, 1)).unwrap();
+
+            panic!(
+                "{} of {} pixels differ from {}. What was drawn is in {}, and the pixels that \
+                 differ are red in {}",
+                mismatches,
+                SCREEN_LENGTH,
+                path.display(),
+                actual_path.display(),
+                diff_path.display()
+            );
+        }
+    }
+
+    fn drawn(state: &GameState) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new();
+        draw(&mut framebuffer, state);
+        framebuffer
+    }
+
+    fn empty_state() -> GameState {
+        let mut state = GameState::new([0; 16], None);
+        for cell in state.cells.iter_mut() {
+            cell.clear();
+        }
+        state.update_enabled_buttons();
+        state
+    }
+
+    #[test]
+    fn a_catalogue_deal() {
+        let state = GameState::from_catalogue(0, None).unwrap();
+
+        assert_matches_golden("deal", &drawn(&state));
+    }
+
+    #[test]
+    fn grabbing_cards_from_the_middle_of_a_column() {
+        let mut state = empty_state();
+        state.cells[0] = vec![CARD_BACK];
+        state.cells[1] = vec![FIRST_BLACK_CARD + 4];
+        state.cells[5] = vec![2];
+        state.cells[8] = vec![FIRST_GREEN_CARD, 8, FIRST_BLACK_CARD + 7, 6];
+        state.cells[10] = vec![FLOWER_CARD, FIRST_BLACK_CARD + 9];
+        state.cells[12] = vec![FIRST_GREEN_CARD + 3];
+        state.selectdrop = true;
+        state.grabpos = 8;
+        state.grabdepth = 2;
+        state.selectpos = 10;
+
+        assert_matches_golden("grab", &drawn(&state));
+    }
+
+    #[test]
+    fn the_button_column_is_selected() {
+        let mut state = empty_state();
+        for pos in 8..12 {
+            state.cells[pos] = vec![FIRST_GREEN_CARD + 5, 0];
+        }
+        state.selectpos = state.ruleset.layout.button_column();
+        state.selectdepth = 0;
+        state.update_enabled_buttons();
+
+        assert_matches_golden("buttons", &drawn(&state));
+    }
+
+    #[test]
+    fn a_hint_is_shown() {
+        let mut state = empty_state();
+        state.cells[8] = vec![FIRST_GREEN_CARD + 2, 5];
+        state.cells[9] = vec![FIRST_BLACK_CARD + 6];
+        state.cells[13] = vec![1];
+        state.selectpos = 13;
+        state.hint = Some(Move::Cards {
+            grabpos: 8,
+            depth: 0,
+            droppos: 9,
+        });
+
+        assert_matches_golden("hint", &drawn(&state));
+    }
+
+    #[test]
+    fn a_won_game_and_the_winning_screen() {
+        let mut state = empty_state();
+        state.cells[4] = vec![FLOWER_CARD];
+        state.cells[5] = vec![MAX_SUIT_NUM];
+        state.cells[6] = vec![FIRST_GREEN_CARD + MAX_SUIT_NUM];
+        state.cells[7] = vec![FIRST_BLACK_CARD + MAX_SUIT_NUM];
+        for pos in 0..3 {
+            state.cells[pos] = vec![CARD_BACK];
+        }
+        state.wins = 12;
+        state.win_done = true;
+
+        let mut framebuffer = drawn(&state);
+        assert_matches_golden("won", &framebuffer);
+
+        draw_winning_screen(&mut framebuffer);
+        assert_matches_golden("winning_screen", &framebuffer);
+    }
+
+    #[test]
+    fn a_smaller_layout() {
+        let layout = Layout::new(2, 3, 6).unwrap();
+        let seed = dealing::catalogue_seed(1).unwrap();
+        let mut state = GameState::with_ruleset(seed, None, Arc::new(Ruleset::for_layout(layout)));
+        state.selectpos = layout.end_of_foundations();
+
+        assert_matches_golden("small_layout", &drawn(&state));
+    }
+}
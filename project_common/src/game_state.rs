@@ -2,64 +2,327 @@ extern crate rand;
 
 use self::rand::{Rng, SeedableRng};
 
+use std::sync::Arc;
+
+use dealing::{self, Dealing, SOLVABLE_DEAL_ATTEMPTS};
+use deck::DeckSpec;
+use history::{History, MoveKind};
 use inner_common::*;
+use ruleset::Ruleset;
+use solver::{self, Budget};
+use {Button, Input};
+
+use vm::{log, FILL_MOVE_TIMER, VM};
 
-use vm::VM;
+/// Small enough that asking for a hint doesn't hold up a frame for long, even in a browser.
+pub const DEFAULT_HINT_BUDGET: Budget = Budget {
+    nodes: 20_000,
+    time: None,
+};
 
 impl GameState {
     pub fn new(seed: [u8; 16], logger: Option<fn(&str) -> ()>) -> GameState {
-        let mut cells: [Vec<u8>; 16] = Default::default();
-
-        let mut deck = Vec::with_capacity(3 * (START_OF_TABLEAU as usize + 4) + 1);
-
-        for i in 1..=MAX_SUIT_NUM {
-            deck.push(i);
-            deck.push(i + 10);
-            deck.push(i + 20);
-        }
+        GameState::with_ruleset(seed, logger, Arc::new(Ruleset::default()))
+    }
 
-        for _ in 1..=4 {
-            deck.push(0);
-            deck.push(10);
-            deck.push(20);
-        }
+    pub fn with_ruleset(
+        seed: [u8; 16],
+        logger: Option<fn(&str) -> ()>,
+        ruleset: Arc<Ruleset>,
+    ) -> GameState {
+        let deck = DeckSpec::for_layout(ruleset.layout);
+        GameState::with_deck(seed, logger, ruleset, deck)
+    }
 
-        deck.push(30);
+    pub fn with_deck(
+        seed: [u8; 16],
+        logger: Option<fn(&str) -> ()>,
+        ruleset: Arc<Ruleset>,
+        deck: DeckSpec,
+    ) -> GameState {
+        let mut cells: Cells = Default::default();
 
         let mut rng = rand::XorShiftRng::from_seed(seed);
 
-        let mut deckpos = START_OF_TABLEAU;
-        while deck.len() > 0 {
-            let index = rng.gen_range(0, deck.len());
-            cells[deckpos as usize].push(deck.swap_remove(index));
-
-            deckpos = if deckpos >= CELLS_MAX_INDEX {
-                START_OF_TABLEAU
-            } else {
-                deckpos + 1
-            };
-        }
+        deck.deal(&mut rng, &mut cells);
 
-        GameState {
+        let mut state = GameState {
             cells,
             wins: 0,
             win_done: false,
             selectdrop: false,
-            selectpos: START_OF_TABLEAU,
+            selectpos: 0,
             selectdepth: 0,
-            grabpos: 1,
+            grabpos: 0,
             grabdepth: 0,
             movetimer: 0,
             vm: VM::new(logger),
             rng,
+            ruleset: Arc::clone(&ruleset),
+            deck,
+            dealing: Dealing::Random,
+            history: History::default(),
+            hint: None,
+            hint_budget: DEFAULT_HINT_BUDGET,
+            deal_search: None,
+            enabled_buttons: Vec::new(),
+        };
+
+        state.interpret_or_log(&ruleset.initialization);
+        state.vm.clear();
+        state.update_enabled_buttons();
+
+        state
+    }
+
+    /// Deals like `new`, except that `reset` only deals games the solver can win within `budget`,
+    /// and so does this if the deal from `seed` is not one of them.
+    pub fn solvable(seed: [u8; 16], logger: Option<fn(&str) -> ()>, budget: Budget) -> GameState {
+        let mut state = GameState::new(seed, logger);
+        state.dealing = Dealing::Solvable(budget);
+
+        if !dealing::is_solvable(seed, Arc::clone(&state.ruleset), state.deck.clone(), budget) {
+            state.reset();
         }
+
+        state
+    }
+
+    /// Deals the catalogue deal with this number, and the ones after it when the game restarts.
+    /// `None` if there is no such deal.
+    pub fn from_catalogue(number: usize, logger: Option<fn(&str) -> ()>) -> Option<GameState> {
+        let seed = dealing::catalogue_seed(number)?;
+
+        let mut state = GameState::new(seed, logger);
+        state.dealing = Dealing::Catalogue(number);
+
+        Some(state)
     }
 
+    /// Deals the next game, picked the way `dealing` says. With `Dealing::Solvable` this can take
+    /// as long as solving `SOLVABLE_DEAL_ATTEMPTS` deals, which `update` spreads over that many
+    /// frames instead.
     pub fn reset(&mut self) {
+        while !self.try_next_deal() {}
+    }
+
+    // Deals the next game and returns true, unless this was a `Dealing::Solvable` attempt that
+    // turned its deal down, in which case `deal_search` counts it and the next call tries again.
+    // The last attempt is dealt whether or not it could be won.
+    fn try_next_deal(&mut self) -> bool {
+        let seed = match self.dealing {
+            Dealing::Random => self.rng.gen(),
+            Dealing::Solvable(budget) => {
+                let seed = self.rng.gen();
+                let attempts = self.deal_search.unwrap_or(0) + 1;
+                let (ruleset, deck) = (Arc::clone(&self.ruleset), self.deck.clone());
+
+                if !dealing::is_solvable(seed, ruleset, deck, budget) {
+                    if attempts < SOLVABLE_DEAL_ATTEMPTS {
+                        self.deal_search = Some(attempts);
+                        return false;
+                    }
+
+                    log(
+                        self.vm.logger,
+                        &format!(
+                            "none of {} deals could be won within the budget, so dealing one \
+                             that may not be winnable",
+                            SOLVABLE_DEAL_ATTEMPTS
+                        ),
+                    );
+                }
+
+                seed
+            }
+            Dealing::Catalogue(number) => {
+                let next = (number + 1) % dealing::catalogue_len();
+                self.dealing = Dealing::Catalogue(next);
+                dealing::catalogue_seed(next).unwrap()
+            }
+        };
+
         let logger = self.vm.logger.take();
+        let ruleset = Arc::clone(&self.ruleset);
+        let deck = self.deck.clone();
+        let dealing = self.dealing;
+        let hint_budget = self.hint_budget;
+
+        *self = GameState::with_deck(seed, logger, ruleset, deck);
+        self.dealing = dealing;
+        self.hint_budget = hint_budget;
+
+        true
+    }
+
+    // Takes the next step towards a new deal, keeping the count of wins.
+    fn continue_reset(&mut self) {
+        let wins = self.wins;
+
+        if self.try_next_deal() {
+            self.wins = wins;
+        }
+    }
+
+    pub fn update(&mut self, input: Input) {
+        if self.deal_search.is_some() {
+            self.continue_reset();
+
+            return;
+        }
+
+        if self.has_won() {
+            if self.win_done {
+                if input.pressed_this_frame(Button::Start) {
+                    self.continue_reset();
+                }
+            } else {
+                self.wins += 1;
+                self.win_done = true;
+            }
+
+            return;
+        }
+
+        if self.movetimer > 0 {
+            self.movetimer -= 1;
+        }
+
+        if self.movetimer == 0 {
+            let before = self.cells.clone();
+
+            if self.try_automove() {
+                self.record(MoveKind::Automove, &before);
+                self.interpret_or_log(&[FILL_MOVE_TIMER]);
+            } else if input.pressed_this_frame(Button::Select) {
+                self.undo();
+            } else if input.pressed_this_frame(Button::Start) {
+                self.redo();
+            } else {
+                let ruleset = Arc::clone(&self.ruleset);
+
+                if let Some(program) = ruleset.button_program(input) {
+                    let kind = if self.selectpos == ruleset.layout.button_column() {
+                        MoveKind::Dragons
+                    } else {
+                        MoveKind::Player
+                    };
+
+                    self.interpret_or_log(program);
+
+                    if self.cells != before {
+                        self.record(kind, &before);
+                    }
+                }
+            }
+
+            self.vm.clear();
+
+            if self.cells != before {
+                self.update_enabled_buttons();
+            }
+        }
+    }
+
+    /// Takes back the last move, along with any automoves it set off. Returns whether there was
+    /// anything to take back.
+    pub fn undo(&mut self) -> bool {
+        let cells = match self.history.undo() {
+            Some(cells) => cells.clone(),
+            None => return false,
+        };
+
+        self.restore(cells);
+
+        true
+    }
+
+    /// Makes the last move that was undone again. Returns whether there was one.
+    pub fn redo(&mut self) -> bool {
+        let cells = match self.history.redo() {
+            Some(cells) => cells.clone(),
+            None => return false,
+        };
+
+        self.restore(cells);
+
+        true
+    }
+
+    /// Looks for the next move within `hint_budget`, and shows it until the cards change. The
+    /// solver only knows the default rules, so there are no hints under any other ruleset.
+    pub fn request_hint(&mut self) {
+        let layout = self.ruleset.layout;
+
+        self.hint = if *self.ruleset == Ruleset::for_layout(layout) {
+            solver::hint(layout, &self.cells, self.hint_budget)
+        } else {
+            None
+        };
+    }
+
+    // Any hint was for the cards as they were.
+    fn record(&mut self, kind: MoveKind, before: &Cells) {
+        let after = self.cells.clone();
+        self.history.record(kind, before.clone(), after);
+        self.hint = None;
+    }
+
+    // Puts the cards back without running any programs. Whatever was grabbed is dropped, since it
+    // may not be there any more.
+    fn restore(&mut self, cells: Cells) {
+        self.cells = cells;
+        self.hint = None;
+        self.selectdrop = false;
+        self.selectdepth = 0;
+    }
+
+    pub fn has_won(&mut self) -> bool {
+        let ruleset = Arc::clone(&self.ruleset);
+
+        self.interpret_flag(&ruleset.win_check, &[])
+    }
+
+    pub fn try_automove(&mut self) -> bool {
+        let ruleset = Arc::clone(&self.ruleset);
+
+        self.interpret_flag(&ruleset.automove, &[])
+    }
+
+    /// Runs the `is_enabled` program of every special button, for the renderer to read back from
+    /// `enabled_buttons`. `update` does this whenever the cards change, so this only needs calling
+    /// after changing them some other way.
+    pub fn update_enabled_buttons(&mut self) {
+        let count = self.ruleset.special_buttons.len();
+
+        self.enabled_buttons = (0..count)
+            .map(|index| self.special_button_enabled(index as u8))
+            .collect();
+    }
+
+    pub fn special_button_enabled(&mut self, index: u8) -> bool {
+        let ruleset = Arc::clone(&self.ruleset);
+
+        match ruleset.special_buttons.get(index as usize) {
+            Some(button) => self.interpret_flag(&button.is_enabled, &[index]),
+            None => false,
+        }
+    }
+
+    // Runs a program that answers a yes or no question by leaving a non-zero value on top of the
+    // stack for yes. `arguments` are pushed before it starts.
+    fn interpret_flag(&mut self, bytecode: &[u8], arguments: &[u8]) -> bool {
+        self.vm.clear();
+        for &argument in arguments {
+            let pushed = self.vm.push(argument);
+            debug_assert!(pushed);
+        }
+
+        self.interpret_or_log(bytecode);
 
-        let new_seed = self.rng.gen();
+        let flag = self.vm.pop().unwrap_or(0) != 0;
+        self.vm.clear();
 
-        *self = GameState::new(new_seed, logger);
+        flag
     }
 }
@@ -0,0 +1,173 @@
+use inner_common::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveKind {
+    /// Anything the player did with the cards, other than collecting dragons.
+    Player,
+    /// Pressing a button in the button column.
+    Dragons,
+    /// A single step of the automove program. These are undone and redone along with the move
+    /// before them.
+    Automove,
+}
+
+/// The cells before and after a move. The foundations only keep their top card, so a move can't
+/// always be worked out backwards from the cells after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub kind: MoveKind,
+    pub before: Cells,
+    pub after: Cells,
+}
+
+/// The moves made so far, and the ones that were undone since the last move.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+    moves: Vec<HistoryEntry>,
+    /// How many of `moves` have been made. The rest can be redone.
+    position: usize,
+}
+
+impl History {
+    pub fn moves(&self) -> &[HistoryEntry] {
+        &self.moves[..self.position]
+    }
+
+    pub fn undone(&self) -> &[HistoryEntry] {
+        &self.moves[self.position..]
+    }
+
+    /// Records a move, which means the moves that were undone can't be redone any more.
+    pub fn record(&mut self, kind: MoveKind, before: Cells, after: Cells) {
+        self.moves.truncate(self.position);
+        self.moves.push(HistoryEntry {
+            kind,
+            before,
+            after,
+        });
+        self.position = self.moves.len();
+    }
+
+    /// Steps back over the last move that was not an automove, and the automoves after it. Returns
+    /// the cells from before that move, or `None` if there is nothing to undo. The automoves made
+    /// right after dealing are not undone, since they would only happen again.
+    pub fn undo(&mut self) -> Option<&Cells> {
+        let position = self.moves[..self.position]
+            .iter()
+            .rposition(|m| m.kind != MoveKind::Automove)?;
+
+        self.position = position;
+
+        Some(&self.moves[position].before)
+    }
+
+    /// Steps forward over the next move, and the automoves after it. Returns the cells from after
+    /// them, or `None` if there is nothing to redo.
+    pub fn redo(&mut self) -> Option<&Cells> {
+        if self.position >= self.moves.len() {
+            return None;
+        }
+
+        let automoves = self.moves[self.position + 1..]
+            .iter()
+            .take_while(|m| m.kind == MoveKind::Automove)
+            .count();
+
+        self.position += 1 + automoves;
+
+        Some(&self.moves[self.position - 1].after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Button, GameState, Input};
+
+    fn cells_with(card: u8) -> Cells {
+        let mut cells: Cells = Default::default();
+        cells[0].push(card);
+        cells
+    }
+
+    fn pressed(button: Button::Ty) -> Input {
+        Input {
+            gamepad: button,
+            previous_gamepad: Button::Ty::empty(),
+        }
+    }
+
+    #[test]
+    fn automoves_are_undone_and_redone_with_the_move_before_them() {
+        let mut history = History::default();
+        history.record(MoveKind::Automove, cells_with(0), cells_with(1));
+        history.record(MoveKind::Player, cells_with(1), cells_with(2));
+        history.record(MoveKind::Automove, cells_with(2), cells_with(3));
+        history.record(MoveKind::Automove, cells_with(3), cells_with(4));
+        history.record(MoveKind::Dragons, cells_with(4), cells_with(5));
+
+        assert_eq!(history.undo(), Some(&cells_with(4)));
+        assert_eq!(history.undo(), Some(&cells_with(1)));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.moves().len(), 1);
+
+        assert_eq!(history.redo(), Some(&cells_with(4)));
+        assert_eq!(history.moves().len(), 4);
+        assert_eq!(history.redo(), Some(&cells_with(5)));
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn recording_a_move_forgets_what_was_undone() {
+        let mut history = History::default();
+        history.record(MoveKind::Player, cells_with(0), cells_with(1));
+        history.record(MoveKind::Player, cells_with(1), cells_with(2));
+
+        history.undo();
+        assert_eq!(history.undone().len(), 1);
+
+        history.record(MoveKind::Player, cells_with(1), cells_with(3));
+        assert_eq!(history.undone().len(), 0);
+        assert_eq!(history.redo(), None);
+        assert_eq!(history.undo(), Some(&cells_with(1)));
+    }
+
+    #[test]
+    fn select_undoes_a_move_and_the_automoves_it_set_off_and_start_redoes_them() {
+        let mut state = GameState::new([0; 16], None);
+        for cell in state.cells.iter_mut() {
+            cell.clear();
+        }
+        state.cells[8] = vec![1, 13];
+        state.cells[9] = vec![25];
+        state.selectpos = 8;
+        let dealt = state.cells.clone();
+
+        // Put the 3 in a free cell, which uncovers the 1 for automove to take.
+        state.update(pressed(Button::A));
+        state.update(pressed(Button::Up));
+        state.update(pressed(Button::A));
+        for _ in 0..8 {
+            state.update(Input::new());
+        }
+        assert_eq!(state.cells[0], vec![13]);
+        assert_eq!(state.cells[5], vec![1]);
+        assert!(state.cells[8].is_empty());
+        let kinds: Vec<_> = state.history.moves().iter().map(|m| m.kind).collect();
+        assert_eq!(kinds, vec![MoveKind::Player, MoveKind::Automove]);
+        let moved = state.cells.clone();
+
+        state.update(pressed(Button::Select));
+        assert_eq!(state.cells, dealt);
+        assert!(!state.selectdrop);
+        assert!(state.history.moves().is_empty());
+
+        // Nothing automoves, because nothing could before the move either.
+        state.update(Input::new());
+        assert_eq!(state.cells, dealt);
+
+        state.update(pressed(Button::Start));
+        assert_eq!(state.cells, moved);
+        assert_eq!(state.history.moves().len(), 2);
+    }
+}
@@ -0,0 +1,134 @@
+// Writes framebuffers out as image files. PPM is as simple as an image format gets, and PNG is the
+// one that everything can show. A whole game can be written out as a GIF. The PNG and GIF formats
+// come from the `png` and `gif` crates.
+
+mod gif;
+
+pub use self::gif::GifEncoder;
+
+use inner_common::*;
+use png::{BitDepth, ColorType, Encoder};
+use Framebuffer;
+
+/// The red, green and blue bytes of each pixel, row by row, with each pixel made into a `scale`
+/// by `scale` square. A `scale` of 0 is treated as 1.
+pub fn rgb_bytes(framebuffer: &Framebuffer, scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let mut bytes = Vec::with_capacity(SCREEN_LENGTH * scale * scale * 3);
+
+    for row in framebuffer.buffer.chunks(SCREEN_WIDTH) {
+        let mut scaled_row = Vec::with_capacity(SCREEN_WIDTH * scale * 3);
+        for &pixel in row {
+            // The pixels are ABGR.
+            let rgb = [pixel as u8, (pixel >> 8) as u8, (pixel >> 16) as u8];
+            for _ in 0..scale {
+                scaled_row.extend_from_slice(&rgb);
+            }
+        }
+
+        for _ in 0..scale {
+            bytes.extend_from_slice(&scaled_row);
+        }
+    }
+
+    bytes
+}
+
+/// A binary PPM, with each pixel made into a `scale` by `scale` square.
+pub fn encode_ppm(framebuffer: &Framebuffer, scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+
+    let mut bytes = format!(
+        "P6\n{} {}\n255\n",
+        SCREEN_WIDTH * scale,
+        SCREEN_HEIGHT * scale
+    )
+    .into_bytes();
+    bytes.extend(rgb_bytes(framebuffer, scale));
+
+    bytes
+}
+
+/// A PNG, with each pixel made into a `scale` by `scale` square.
+pub fn encode_png(framebuffer: &Framebuffer, scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut bytes, width as u32, height as u32);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+
+        // Writing to a `Vec` can't fail, and the size and pixels always agree.
+        let mut writer = encoder.write_header().expect("writing the PNG header");
+        writer
+            .write_image_data(&rgb_bytes(framebuffer, scale))
+            .expect("writing the PNG pixels");
+    }
+
+    bytes
+}
+
+/// For comparing what was drawn with the images the tests keep. The width, height and RGB bytes
+/// of an 8 bit RGB PNG, like the ones `encode_png` writes.
+#[cfg(test)]
+pub fn decode_png(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>), String> {
+    use png::Decoder;
+
+    let mut reader = Decoder::new(bytes)
+        .read_info()
+        .map_err(|error| error.to_string())?;
+    let mut rgb = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut rgb)
+        .map_err(|error| error.to_string())?;
+
+    if (info.color_type, info.bit_depth) != (ColorType::Rgb, BitDepth::Eight) {
+        return Err("only 8 bit RGB PNGs can be read".to_owned());
+    }
+    rgb.truncate(info.buffer_size());
+
+    Ok((info.width as usize, info.height as usize, rgb))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_colour_compresses_well() {
+        // Over 768K of pixels, which are all the same colour.
+        let screen = encode_png(&Framebuffer::new(), 4);
+        assert!(screen.len() < 10_000, "{} bytes", screen.len());
+    }
+
+    #[test]
+    fn scaling_makes_each_pixel_a_square() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.buffer[1] = PALETTE[8];
+        framebuffer.buffer[SCREEN_WIDTH] = PALETTE[11];
+
+        let rgb = rgb_bytes(&framebuffer, 2);
+        let pixel = |x: usize, y: usize| {
+            let i = (y * SCREEN_WIDTH * 2 + x) * 3;
+            [rgb[i], rgb[i + 1], rgb[i + 2]]
+        };
+        let red = [0xff, 0x00, 0x4d];
+
+        assert_eq!(rgb.len(), SCREEN_LENGTH * 4 * 3);
+        assert_eq!(pixel(0, 0), pixel(1, 1));
+        assert_eq!(pixel(2, 0), red);
+        assert_eq!(pixel(3, 1), red);
+        assert_ne!(pixel(4, 0), red);
+        assert_eq!(pixel(0, 2), pixel(1, 3));
+        assert_ne!(pixel(0, 2), pixel(0, 0));
+
+        let ppm = encode_ppm(&framebuffer, 2);
+        assert!(ppm.starts_with(b"P6\n256 256\n255\n"));
+        assert_eq!(ppm.len(), 15 + rgb.len());
+
+        let png = encode_png(&framebuffer, 2);
+        assert_eq!(decode_png(&png), Ok((256, 256, rgb)));
+    }
+}
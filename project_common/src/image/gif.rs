@@ -0,0 +1,256 @@
+// Animated GIFs, written with the `gif` crate. Everything the game draws is a `PALETTE` colour, so
+// the palette is the GIF's global colour table, and the pixels are written as indices into it.
+// Frames that look the same as the one before them are not written again, the one before them is
+// just shown for longer, and each frame that is written only covers the part of the screen that
+// changed.
+
+use std::borrow::Cow;
+
+use gif::{DisposalMethod, Encoder, Frame, Repeat};
+
+use inner_common::*;
+use Framebuffer;
+
+const FRAMES_PER_SECOND: usize = 60;
+// Browsers show frames with a delay shorter than this many hundredths of a second for a tenth of a
+// second instead, so frames that would be shown for less time than this are skipped.
+const MIN_DELAY: usize = 2;
+
+/// Writes the frames of the game as they are drawn into an animated GIF that plays at the same
+/// speed, and loops.
+pub struct GifEncoder {
+    scale: usize,
+    encoder: Encoder<Vec<u8>>,
+    // What is on the screen once every frame so far has been written.
+    shown: Option<Vec<u8>>,
+    // The last frame that will be written. It is held back until the next one, since frames too
+    // short to be seen add their time to its delay instead.
+    held: Option<Frame<'static>>,
+    // The frame that has not been written yet, and how many frames of the game it lasts.
+    pending: Option<(Vec<u8>, usize)>,
+    elapsed_frames: usize,
+    // In hundredths of a second.
+    written_delay: usize,
+}
+
+impl GifEncoder {
+    /// Each pixel is made into a `scale` by `scale` square. A `scale` of 0 is treated as 1.
+    pub fn new(scale: usize) -> GifEncoder {
+        let scale = scale.max(1);
+
+        let palette: Vec<u8> = PALETTE
+            .iter()
+            // The colours are ABGR.
+            .flat_map(|&colour| vec![colour as u8, (colour >> 8) as u8, (colour >> 16) as u8])
+            .collect();
+
+        // Writing to a `Vec` can't fail, and the screen and palette always fit in a GIF.
+        let mut encoder = Encoder::new(
+            Vec::new(),
+            (SCREEN_WIDTH * scale) as u16,
+            (SCREEN_HEIGHT * scale) as u16,
+            &palette,
+        )
+        .expect("writing the GIF header");
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .expect("writing the GIF header");
+
+        GifEncoder {
+            scale,
+            encoder,
+            shown: None,
+            held: None,
+            pending: None,
+            elapsed_frames: 0,
+            written_delay: 0,
+        }
+    }
+
+    /// Adds what was drawn on the next frame of the game.
+    pub fn add_frame(&mut self, framebuffer: &Framebuffer) {
+        let indices: Vec<u8> = framebuffer
+            .buffer
+            .iter()
+            .map(|&pixel| palette_index(pixel))
+            .collect();
+
+        if let Some((ref pending, ref mut frames)) = self.pending {
+            if *pending == indices {
+                *frames += 1;
+                return;
+            }
+        }
+
+        self.write_pending(false);
+        self.pending = Some((indices, 1));
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.write_pending(true);
+        self.write_held();
+
+        self.encoder.into_inner().expect("writing the GIF trailer")
+    }
+
+    fn write_pending(&mut self, last: bool) {
+        let (indices, frames) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        self.elapsed_frames += frames;
+        let end = (self.elapsed_frames * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND;
+        let mut delay = end - self.written_delay;
+
+        let changed = match self.shown {
+            Some(ref shown) if delay >= MIN_DELAY || last => changed_rectangle(shown, &indices),
+            // Too short to be seen, so the frame before it stays up instead.
+            Some(_) => None,
+            None => Some((0, 0, SCREEN_WIDTH, SCREEN_HEIGHT)),
+        };
+
+        match changed {
+            Some(rectangle) => {
+                delay = delay.max(MIN_DELAY);
+                self.write_held();
+                self.held = Some(self.frame(&indices, rectangle, delay));
+                self.shown = Some(indices);
+            }
+            None => {
+                if let Some(ref mut held) = self.held {
+                    held.delay = (held.delay as usize + delay).min(0xffff) as u16;
+                }
+            }
+        }
+
+        self.written_delay += delay;
+    }
+
+    fn write_held(&mut self) {
+        if let Some(held) = self.held.take() {
+            self.encoder
+                .write_frame(&held)
+                .expect("writing a GIF frame");
+        }
+    }
+
+    // A frame that leaves itself in place for the next one to be drawn over.
+    fn frame(
+        &self,
+        indices: &[u8],
+        rectangle: (usize, usize, usize, usize),
+        delay: usize,
+    ) -> Frame<'static> {
+        let (left, top, width, height) = rectangle;
+        let scale = self.scale;
+
+        let mut pixels = Vec::with_capacity(width * height * scale * scale);
+        for row in indices.chunks(SCREEN_WIDTH).skip(top).take(height) {
+            let start = pixels.len();
+            for &index in row[left..left + width].iter() {
+                for _ in 0..scale {
+                    pixels.push(index);
+                }
+            }
+            for _ in 1..scale {
+                pixels.extend_from_within(start..);
+            }
+        }
+
+        Frame {
+            delay: delay as u16,
+            dispose: DisposalMethod::Keep,
+            left: (left * scale) as u16,
+            top: (top * scale) as u16,
+            width: (width * scale) as u16,
+            height: (height * scale) as u16,
+            buffer: Cow::Owned(pixels),
+            ..Frame::default()
+        }
+    }
+}
+
+// The palette colour closest to `pixel`, which is normally the same colour.
+fn palette_index(pixel: u32) -> u8 {
+    if let Some(index) = PALETTE.iter().position(|&colour| colour == pixel) {
+        return index as u8;
+    }
+
+    let channels = |colour: u32| (0..3).map(move |i| ((colour >> (i * 8)) & 0xff) as i32);
+
+    (0..PALETTE.len())
+        .min_by_key(|&i| {
+            channels(pixel)
+                .zip(channels(PALETTE[i]))
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<i32>()
+        })
+        .unwrap() as u8
+}
+
+// The left, top, width and height of the smallest rectangle around the pixels that differ.
+fn changed_rectangle(before: &[u8], after: &[u8]) -> Option<(usize, usize, usize, usize)> {
+    let mut changed = (0..SCREEN_LENGTH).filter(|&i| before[i] != after[i]);
+
+    let first = changed.next()?;
+    let (mut left, top) = (first % SCREEN_WIDTH, first / SCREEN_WIDTH);
+    let (mut right, mut bottom) = (left, top);
+    for i in changed {
+        let (x, y) = (i % SCREEN_WIDTH, i / SCREEN_WIDTH);
+        left = left.min(x);
+        right = right.max(x);
+        bottom = y;
+    }
+
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::DecodeOptions;
+
+    #[test]
+    fn only_frames_that_change_are_written() {
+        let mut framebuffer = Framebuffer::new();
+        let mut gif = GifEncoder::new(2);
+
+        for _ in 0..46 {
+            gif.add_frame(&framebuffer);
+        }
+        // A single frame, which is too short to be seen here.
+        framebuffer.buffer[SCREEN_WIDTH + 5] = PALETTE[8];
+        gif.add_frame(&framebuffer);
+        framebuffer.buffer[SCREEN_WIDTH * 3 + 2] = PALETTE[12];
+        for _ in 0..60 {
+            gif.add_frame(&framebuffer);
+        }
+        let bytes = gif.finish();
+
+        let mut decoder = DecodeOptions::new().read_info(&bytes[..]).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (256, 256));
+        assert_eq!(
+            &decoder.global_palette().unwrap()[..6],
+            &[0x00, 0x00, 0x00, 0x1d, 0x2b, 0x53]
+        );
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((
+                frame.delay,
+                frame.dispose,
+                (frame.left, frame.top, frame.width, frame.height),
+            ));
+        }
+
+        assert_eq!(
+            frames,
+            vec![
+                (78, DisposalMethod::Keep, (0, 0, 256, 256)),
+                // Covers both of the pixels that changed, scaled up.
+                (100, DisposalMethod::Keep, (4, 2, 8, 6)),
+            ]
+        );
+    }
+}
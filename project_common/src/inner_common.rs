@@ -27,6 +27,8 @@ pub const MOVE_TIMER_MAX: u8 = 3;
 pub const MAX_SUIT_NUM: u8 = 9;
 
 pub const BUTTON_COLUMN: u8 = 3;
+/// How many buttons fit in the button column, which is one for each suit of dragons.
+pub const SPECIAL_BUTTON_COUNT: u8 = 3;
 pub const FLOWER_FOUNDATION: u8 = 4;
 pub const START_OF_FOUNDATIONS: u8 = 5;
 pub const END_OF_FOUNDATIONS: u8 = 7;
@@ -40,7 +42,14 @@ pub const CARD_BACK: u8 = 31;
 
 pub type Cells = [Vec<u8>; CELLS_MAX_INDEX as usize + 1];
 
+use dealing::Dealing;
+use deck::DeckSpec;
+use history::History;
+use moves::Move;
 use rand::XorShiftRng;
+use ruleset::Ruleset;
+use solver::Budget;
+use std::sync::Arc;
 use vm::VM;
 
 #[derive(Clone, Debug)]
@@ -56,6 +65,21 @@ pub struct GameState {
     pub movetimer: u8,
     pub vm: VM,
     pub rng: XorShiftRng,
+    pub ruleset: Arc<Ruleset>,
+    pub deck: DeckSpec,
+    /// How the next deal is picked when the game restarts.
+    pub dealing: Dealing,
+    pub history: History,
+    /// The move to suggest to the player, until the cards next move.
+    pub hint: Option<Move>,
+    /// How hard `request_hint` looks for a hint.
+    pub hint_budget: Budget,
+    /// How many deals have been turned down so far, while `update` is looking for a
+    /// `Dealing::Solvable` one to deal next. It looks at one a frame.
+    pub deal_search: Option<usize>,
+    /// Whether each of the ruleset's special buttons can be pressed, as of the last time the cards
+    /// changed.
+    pub enabled_buttons: Vec<bool>,
 }
 
 pub const GFX_WIDTH: usize = 128;
@@ -0,0 +1,33 @@
+use Button;
+
+/// The button a key stands for, going by the name the browser gives the key in
+/// `KeyboardEvent.key`. `right` says whether the key was on the right side of the keyboard, for
+/// keys like Shift that are on both.
+pub fn button_for_key(key: &str, right: bool) -> Option<Button::Ty> {
+    let button = match key {
+        "Enter" => Button::Start,
+        "Shift" if right => Button::Select,
+        "ArrowUp" => Button::Up,
+        "ArrowLeft" => Button::Left,
+        "ArrowRight" => Button::Right,
+        "ArrowDown" => Button::Down,
+
+        // On Edge the arrows have different names
+        // for some reason.
+        "Up" => Button::Up,
+        "Left" => Button::Left,
+        "Right" => Button::Right,
+        "Down" => Button::Down,
+
+        "z" => Button::A,
+        "x" => Button::B,
+
+        // For those using the Dvorak layout.
+        ";" => Button::A,
+        "q" => Button::B,
+
+        _ => return None,
+    };
+
+    Some(button)
+}
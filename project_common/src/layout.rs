@@ -0,0 +1,142 @@
+use inner_common::*;
+
+/// How many cards wide each row of the board can be, on a 128 pixel wide screen.
+pub const ROW_MAX_LEN: u8 = 8;
+
+/// Which cells are free cells, foundations and tableau columns. The top row holds the free cells,
+/// then the button column, the flower foundation and the foundations for the suits, and the
+/// tableau comes after it, so the cells of the default layout are where the constants in
+/// `inner_common` say they are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Layout {
+    free_cells: u8,
+    foundations: u8,
+    tableau: u8,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            free_cells: BUTTON_COLUMN,
+            foundations: END_OF_FOUNDATIONS - START_OF_FOUNDATIONS + 1,
+            tableau: CELLS_MAX_INDEX - START_OF_TABLEAU + 1,
+        }
+    }
+}
+
+impl Layout {
+    /// `foundations` does not count the flower foundation. Returns `None` unless there is at least
+    /// one of each kind of cell and both rows fit on the screen.
+    pub fn new(free_cells: u8, foundations: u8, tableau: u8) -> Option<Self> {
+        let top_row_len = free_cells as usize + 2 + foundations as usize;
+
+        if free_cells == 0
+            || foundations == 0
+            || tableau == 0
+            || top_row_len > ROW_MAX_LEN as usize
+            || tableau > ROW_MAX_LEN
+        {
+            return None;
+        }
+
+        Some(Layout {
+            free_cells,
+            foundations,
+            tableau,
+        })
+    }
+
+    pub fn free_cells(&self) -> u8 {
+        self.free_cells
+    }
+
+    pub fn foundations(&self) -> u8 {
+        self.foundations
+    }
+
+    pub fn tableau(&self) -> u8 {
+        self.tableau
+    }
+
+    pub fn button_column(&self) -> u8 {
+        self.free_cells
+    }
+
+    pub fn flower_foundation(&self) -> u8 {
+        self.free_cells + 1
+    }
+
+    pub fn start_of_foundations(&self) -> u8 {
+        self.free_cells + 2
+    }
+
+    pub fn end_of_foundations(&self) -> u8 {
+        self.start_of_foundations() + self.foundations - 1
+    }
+
+    pub fn start_of_tableau(&self) -> u8 {
+        self.end_of_foundations() + 1
+    }
+
+    pub fn cells_max_index(&self) -> u8 {
+        self.start_of_tableau() + self.tableau - 1
+    }
+
+    pub fn is_tableau(&self, pos: u8) -> bool {
+        pos >= self.start_of_tableau() && pos <= self.cells_max_index()
+    }
+
+    /// Maps any byte onto a cell of this layout, the way `& 15` does for the default one.
+    pub fn wrap(&self, pos: u8) -> usize {
+        pos as usize % (self.cells_max_index() as usize + 1)
+    }
+
+    /// The positions of the cells, for the assembler. These shadow the constants of the same names
+    /// in `SYMBOLS`.
+    pub fn symbols(&self) -> [(&'static str, u8); 6] {
+        [
+            ("BUTTON_COLUMN", self.button_column()),
+            ("FLOWER_FOUNDATION", self.flower_foundation()),
+            ("START_OF_FOUNDATIONS", self.start_of_foundations()),
+            ("END_OF_FOUNDATIONS", self.end_of_foundations()),
+            ("START_OF_TABLEAU", self.start_of_tableau()),
+            ("CELLS_MAX_INDEX", self.cells_max_index()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_layout_matches_the_constants() {
+        let layout = Layout::default();
+
+        assert_eq!(Layout::new(3, 3, 8), Some(layout));
+        assert_eq!(layout.button_column(), BUTTON_COLUMN);
+        assert_eq!(layout.flower_foundation(), FLOWER_FOUNDATION);
+        assert_eq!(layout.start_of_foundations(), START_OF_FOUNDATIONS);
+        assert_eq!(layout.end_of_foundations(), END_OF_FOUNDATIONS);
+        assert_eq!(layout.start_of_tableau(), START_OF_TABLEAU);
+        assert_eq!(layout.cells_max_index(), CELLS_MAX_INDEX);
+
+        for pos in 0..=255 {
+            assert_eq!(layout.wrap(pos), pos as usize & 15);
+        }
+    }
+
+    #[test]
+    fn layouts_have_to_fit_on_the_screen() {
+        assert_eq!(Layout::new(4, 3, 8), None);
+        assert_eq!(Layout::new(3, 3, 9), None);
+        assert_eq!(Layout::new(0, 3, 8), None);
+        assert_eq!(Layout::new(3, 0, 8), None);
+        assert_eq!(Layout::new(3, 3, 0), None);
+
+        let layout = Layout::new(2, 3, 6).unwrap();
+        assert_eq!(layout.start_of_tableau(), 7);
+        assert_eq!(layout.cells_max_index(), 12);
+        assert_eq!(layout.wrap(13), 0);
+    }
+}
@@ -11,6 +11,8 @@ macro_rules! last_unchecked {
 #[macro_use]
 extern crate bitflags;
 
+extern crate gif;
+extern crate png;
 extern crate rand;
 
 pub mod inner_common;
@@ -20,9 +22,48 @@ pub mod rendering;
 pub use rendering::draw_winning_screen;
 pub use rendering::Framebuffer;
 
+pub mod image;
+pub use image::{encode_png, encode_ppm, GifEncoder};
+
+pub mod keyboard;
+pub use keyboard::button_for_key;
+
+pub mod layout;
+pub use layout::Layout;
+
+pub mod deck;
+pub use deck::{DealPattern, DeckSpec};
+
+pub mod game;
+pub use game::{draw, update_and_render};
+
+pub mod moves;
+pub use moves::Move;
+
+pub mod dealing;
+pub use dealing::Dealing;
+
+pub mod history;
+pub use history::{History, HistoryEntry, MoveKind};
+
 pub mod game_state;
 pub use game_state::*;
 
+pub mod replay;
+pub use replay::{Replay, ReplayError};
+
+pub mod script;
+pub use script::{parse_script, script_for_moves, ScriptError};
+
+pub mod ruleset;
+pub use ruleset::{Ruleset, SpecialButton};
+
+pub mod text;
+pub use text::{card_text, cell_name};
+
+pub mod solver;
+pub use solver::{hint, solve, Budget, Solution};
+
 pub mod vm;
 pub use vm::*;
 
@@ -62,28 +103,28 @@ pub mod Button {
     }
 }
 
-pub fn movecards(state: &mut GameState, grabpos: u8, grabdepth: u8, droppos: u8) {
-    let grabpos = grabpos as usize & 15;
+pub fn movecards(layout: Layout, cells: &mut Cells, grabpos: u8, grabdepth: u8, droppos: u8) {
+    let grabpos = layout.wrap(grabpos);
     let grabdepth = grabdepth as usize;
-    let droppos = droppos as usize & 15;
-    if droppos <= END_OF_FOUNDATIONS as usize {
-        if let Some(last) = state.cells[grabpos].pop() {
-            if state.cells[droppos].len() > 0 {
-                state.cells[droppos][0] = last;
+    let droppos = layout.wrap(droppos);
+    if droppos <= layout.end_of_foundations() as usize {
+        if let Some(last) = cells[grabpos].pop() {
+            if !cells[droppos].is_empty() {
+                cells[droppos][0] = last;
             } else {
-                state.cells[droppos].push(last);
+                cells[droppos].push(last);
             }
         }
     } else {
-        let len = state.cells[grabpos].len();
+        let len = cells[grabpos].len();
 
         if len == 0 || grabdepth > len - 1 {
             return;
         }
 
-        let temp: Vec<_> = state.cells[grabpos].drain(len - 1 - grabdepth..).collect();
+        let temp: Vec<_> = cells[grabpos].drain(len - 1 - grabdepth..).collect();
 
-        state.cells[droppos].extend(temp.into_iter());
+        cells[droppos].extend(temp.into_iter());
     }
 }
 
@@ -103,15 +144,15 @@ pub fn getcardnum(card: u8) -> u8 {
     card - (getsuit(card) * 10)
 }
 
-pub fn canmovedragons(state: &GameState, suit: u8) -> bool {
+pub fn canmovedragons(layout: Layout, cells: &Cells, suit: u8) -> bool {
     if suit >= 3 {
         return false;
     }
 
     let mut count = 0;
-    for i in 0..=CELLS_MAX_INDEX {
+    for i in 0..=layout.cells_max_index() {
         let i = i as usize;
-        if state.cells[i].len() > 0 && last_unchecked!(state.cells[i]) == suit * 10 {
+        if !cells[i].is_empty() && last_unchecked!(cells[i]) == suit * 10 {
             count += 1;
         }
     }
@@ -120,56 +161,119 @@ pub fn canmovedragons(state: &GameState, suit: u8) -> bool {
         return false;
     }
 
-    for i in 0..BUTTON_COLUMN {
+    for i in 0..layout.button_column() {
         let i = i as usize;
-        if state.cells[i].len() == 0 || last_unchecked!(state.cells[i]) == suit * 10 {
+        if cells[i].is_empty() || last_unchecked!(cells[i]) == suit * 10 {
             return true;
         }
     }
-    return false;
+    false
 }
 
-pub fn movedragons(state: &mut GameState) {
-    let suit = state.selectdepth;
+pub fn movedragons(layout: Layout, cells: &mut Cells, suit: u8) {
     let mut moveto = None;
 
-    for i in 0..BUTTON_COLUMN {
+    for i in 0..layout.button_column() {
         let i = i as usize;
-        if state.cells[i].len() != 0
-            && last_unchecked!(state.cells[i]) == suit * 10
+        if cells[i].len() != 0
+            && last_unchecked!(cells[i]) == suit * 10
             && moveto.is_none()
         {
             moveto = Some(i);
         }
     }
     if moveto.is_none() {
-        for i in 0..BUTTON_COLUMN {
+        for i in 0..layout.button_column() {
             let i = i as usize;
-            if state.cells[i].len() == 0 {
+            if cells[i].len() == 0 {
                 moveto = Some(i);
                 break;
             }
         }
     }
 
-    for i in 0..=CELLS_MAX_INDEX {
+    for i in 0..=layout.cells_max_index() {
         let i = i as usize;
-        if state.cells[i].len() != 0 && last_unchecked!(state.cells[i]) == suit * 10 {
-            state.cells[i].pop();
+        if cells[i].len() != 0 && last_unchecked!(cells[i]) == suit * 10 {
+            cells[i].pop();
         }
     }
 
     if let Some(moveto) = moveto {
         let moveto = moveto as usize;
-        state.cells[moveto].push(CARD_BACK);
+        cells[moveto].push(CARD_BACK);
     }
 }
 
-pub fn cangrab(cells: &Cells, pos: u8, depth: u8) -> bool {
-    let pos = pos & 15;
+pub fn haswon(layout: Layout, cells: &Cells) -> bool {
+    for i in layout.start_of_tableau()..=layout.cells_max_index() {
+        let i = i as usize;
+        if !cells[i].is_empty() {
+            return false;
+        }
+    }
+    true
+}
+
+pub fn automove(layout: Layout, cells: &mut Cells) -> bool {
+    let min_free_card_num = {
+        let mut min_foundation_card_num = None;
+
+        for i in layout.start_of_foundations()..layout.start_of_tableau() {
+            let i = i as usize;
+            let val = if !cells[i].is_empty() {
+                let card = last_unchecked!(cells[i]);
+                getcardnum(card)
+            } else {
+                0
+            };
+            if min_foundation_card_num.map(|v| val < v).unwrap_or(true) {
+                min_foundation_card_num = Some(val);
+            }
+        }
+
+        min_foundation_card_num.unwrap_or(255).wrapping_add(1)
+    };
+
+    for i in 0..=layout.cells_max_index() {
+        if !(layout.button_column()..layout.start_of_tableau()).contains(&i)
+            && !cells[i as usize].is_empty()
+        {
+            let card = last_unchecked!(cells[i as usize]);
+            if card == FLOWER_CARD {
+                movecards(layout, cells, i, 0, layout.flower_foundation());
+                return true;
+            } else if getcardnum(card) == min_free_card_num && card != CARD_BACK {
+                let suit = getsuit(card);
+                for i2 in layout.start_of_foundations()..layout.start_of_tableau() {
+                    if !cells[i2 as usize].is_empty() {
+                        let card2 = last_unchecked!(cells[i2 as usize]);
+                        if getsuit(card2) == suit {
+                            movecards(layout, cells, i, 0, i2);
+                            return true;
+                        }
+                    }
+                }
+                for i2 in layout.start_of_foundations()..layout.start_of_tableau() {
+                    if cells[i2 as usize].is_empty() {
+                        movecards(layout, cells, i, 0, i2);
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    false
+}
+
+pub fn cangrab(layout: Layout, cells: &Cells, pos: u8, depth: u8) -> bool {
+    let pos = layout.wrap(pos) as u8;
 
     let selection = getselection(cells, pos, depth);
-    if selection.len() == 0 || (pos >= FLOWER_FOUNDATION && pos < START_OF_TABLEAU) {
+    if selection.len() == 0
+        || (pos >= layout.flower_foundation() && pos < layout.start_of_tableau())
+    {
         return false;
     }
 
@@ -198,6 +302,44 @@ pub fn cangrab(cells: &Cells, pos: u8, depth: u8) -> bool {
     return true;
 }
 
+// The checks the default A program makes before dropping what was grabbed, so this should only be
+// asked about grabs that `cangrab` allows. The button column is never somewhere to drop cards, and
+// only automove puts anything on the flower foundation.
+pub fn candrop(layout: Layout, cells: &Cells, grabpos: u8, grabdepth: u8, droppos: u8) -> bool {
+    let grabpos = layout.wrap(grabpos) as u8;
+    let droppos = layout.wrap(droppos) as u8;
+
+    let grabcard = match getselection(cells, grabpos, grabdepth).first() {
+        Some(&card) => card,
+        None => return false,
+    };
+    let dropcard = cells[droppos as usize].last().cloned();
+
+    if droppos < layout.button_column() {
+        dropcard.is_none() && grabdepth == 0
+    } else if droppos <= layout.flower_foundation() {
+        false
+    } else if droppos <= layout.end_of_foundations() {
+        grabdepth == 0
+            && match dropcard {
+                None => getcardnum(grabcard) == 1,
+                Some(dropcard) => {
+                    getsuit(grabcard) == getsuit(dropcard)
+                        && getcardnum(grabcard) == getcardnum(dropcard) + 1
+                }
+            }
+    } else {
+        match dropcard {
+            None => true,
+            Some(dropcard) => {
+                getsuit(grabcard) != getsuit(dropcard)
+                    && getcardnum(grabcard) != 0
+                    && getcardnum(grabcard) + 1 == getcardnum(dropcard)
+            }
+        }
+    }
+}
+
 const SMALLEST_PO2_MINUS_1_GREATER_THAN_MAX_CARD: usize = 31;
 
 pub fn getselection(cells: &Cells, pos: u8, depth: u8) -> Vec<u8> {
@@ -0,0 +1,161 @@
+use inner_common::*;
+use {candrop, cangrab, canmovedragons, movecards, movedragons, Layout};
+
+/// Something a player can do to the cards. The automoves that follow are not part of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Move {
+    /// Grab the cards from `depth` down in `grabpos`, and drop them on `droppos`.
+    Cards { grabpos: u8, depth: u8, droppos: u8 },
+    /// Press the button that collects the dragons of `suit`.
+    Dragons { suit: u8 },
+}
+
+impl Move {
+    /// Makes the move, without checking that it is legal.
+    pub fn apply(&self, layout: Layout, cells: &mut Cells) {
+        match *self {
+            Move::Cards {
+                grabpos,
+                depth,
+                droppos,
+            } => movecards(layout, cells, grabpos, depth, droppos),
+            Move::Dragons { suit } => movedragons(layout, cells, suit),
+        }
+    }
+}
+
+/// Every move the default rules allow, which are the ones their A and dragon button programs make.
+/// The dragon collections come first, and then the card moves in order of where they are grabbed
+/// from, how deep, and where they are dropped.
+pub fn legal_moves(layout: Layout, cells: &Cells) -> Vec<Move> {
+    let mut moves: Vec<Move> = (0..3)
+        .filter(|&suit| canmovedragons(layout, cells, suit))
+        .map(|suit| Move::Dragons { suit })
+        .collect();
+
+    for grabpos in 0..=layout.cells_max_index() {
+        for depth in 0..cells[grabpos as usize].len() as u8 {
+            // A grab is only allowed if every shallower one is.
+            if !cangrab(layout, cells, grabpos, depth) {
+                break;
+            }
+
+            for droppos in 0..=layout.cells_max_index() {
+                if candrop(layout, cells, grabpos, depth, droppos) {
+                    moves.push(Move::Cards {
+                        grabpos,
+                        depth,
+                        droppos,
+                    });
+                }
+            }
+        }
+    }
+
+    moves
+}
+
+impl GameState {
+    pub fn legal_moves(&self) -> Vec<Move> {
+        legal_moves(self.ruleset.layout, &self.cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::collections::HashSet;
+    use vm::instructions::HANDLE_BUTTON_PRESS;
+
+    // What pressing A does, or pressing A on a dragon button, starting with nothing grabbed.
+    fn make_with_a_button(state: &GameState, move_: Move) -> Option<Cells> {
+        let mut state = state.clone();
+        let a = state.ruleset.a.clone();
+
+        match move_ {
+            Move::Cards {
+                grabpos,
+                depth,
+                droppos,
+            } => {
+                state.selectdrop = false;
+                state.selectpos = grabpos;
+                state.selectdepth = depth;
+                state.interpret(&a).unwrap();
+                if !state.selectdrop {
+                    return None;
+                }
+
+                state.selectpos = droppos;
+                state.movetimer = 0;
+                state.interpret(&a).unwrap();
+                if state.movetimer == 0 {
+                    return None;
+                }
+            }
+            Move::Dragons { suit } => {
+                let before = state.cells.clone();
+                state.selectdepth = suit;
+                state.interpret(&[HANDLE_BUTTON_PRESS]).unwrap();
+                if state.cells == before {
+                    return None;
+                }
+            }
+        }
+
+        Some(state.cells)
+    }
+
+    #[test]
+    fn the_legal_moves_are_the_ones_the_a_button_makes() {
+        let mut rng = XorShiftRng::from_seed([5; 16]);
+        let mut move_count = 0;
+
+        for seed in 0..32 {
+            let mut state = GameState::new([seed; 16], None);
+            let layout = state.ruleset.layout;
+            let cell_count = layout.cells_max_index() + 1;
+
+            for _ in 0..16 {
+                state.movetimer = 0;
+                let moves = state.legal_moves();
+                let legal: HashSet<_> = moves.iter().cloned().collect();
+                assert_eq!(legal.len(), moves.len());
+
+                let mut everything: Vec<_> = (0..4).map(|suit| Move::Dragons { suit }).collect();
+                for grabpos in 0..cell_count {
+                    for depth in 0..state.cells[grabpos as usize].len() as u8 {
+                        for droppos in 0..cell_count {
+                            if droppos != layout.button_column() {
+                                everything.push(Move::Cards {
+                                    grabpos,
+                                    depth,
+                                    droppos,
+                                });
+                            }
+                        }
+                    }
+                }
+
+                for move_ in everything {
+                    let made = make_with_a_button(&state, move_);
+                    assert_eq!(made.is_some(), legal.contains(&move_), "{:?}", move_);
+
+                    if let Some(cells) = made {
+                        let mut expected = state.cells.clone();
+                        move_.apply(layout, &mut expected);
+                        assert_eq!(cells, expected, "{:?}", move_);
+                    }
+                }
+
+                move_count += moves.len();
+                if let Some(&move_) = rng.choose(&moves) {
+                    move_.apply(layout, &mut state.cells);
+                }
+            }
+        }
+
+        assert!(move_count > 1000, "only {} moves", move_count);
+    }
+}
@@ -439,10 +439,12 @@ impl Framebuffer {
         let d_x_max = d_x + s_w;
         let d_y_max = d_y + s_h;
 
-        let mut current_s_y = s_y + s_h - 1;
-        for y in d_y..d_y_max {
-            let mut current_s_x = s_x + s_w - 1;
-            for x in d_x..d_x_max {
+        // Counting down from the far edge of the sprite would go below zero after the last pixel
+        // of a sprite at the edge of `GFX`.
+        for (i, y) in (d_y..d_y_max).enumerate() {
+            let current_s_y = s_y + s_h - 1 - i;
+            for (j, x) in (d_x..d_x_max).enumerate() {
+                let current_s_x = s_x + s_w - 1 - j;
                 let colour = GFX[current_s_x + current_s_y * S_WIDTH] as usize;
                 //make purple transparent
                 if colour != 2 {
@@ -451,9 +453,7 @@ impl Framebuffer {
                         self.buffer[index] = PALETTE[colour];
                     }
                 }
-                current_s_x -= 1;
             }
-            current_s_y -= 1;
         }
     }
 
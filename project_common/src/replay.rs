@@ -0,0 +1,440 @@
+// A replay is the seed and the state of the gamepad on every frame, which is all `GameState`
+// depends on. The bytes are:
+//
+//     b"SZRP"     magic
+//     2           version
+//     [u8; 16]    seed
+//     dealing     how the games after the first are dealt: 0 for `Dealing::Random`, 1 for
+//                 `Dealing::Solvable` then the budget's nodes and either 0 for no time limit or 1
+//                 and the time limit in milliseconds, or 2 for `Dealing::Catalogue` then the number
+//     runs        until the end: a gamepad byte, then how many frames in a row it was held for,
+//                 which is at least 1
+//
+// The numbers are unsigned LEB128. Replays are played with the default ruleset and deck. Version 1
+// replays are the same without the dealing, and deal at random after the first game.
+
+use std::fmt;
+use std::iter;
+use std::slice;
+use std::time::Duration;
+
+use solver::Budget;
+use {Button, Dealing, GameState, Input};
+
+pub const REPLAY_MAGIC: &[u8; 4] = b"SZRP";
+pub const REPLAY_VERSION: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The bytes end in the middle of the header or a run.
+    Truncated,
+    /// A run is zero frames long, or too long to count.
+    BadRunLength,
+    /// The dealing is not one there is, or its numbers are too big.
+    BadDealing,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::BadMagic => write!(f, "not a replay"),
+            ReplayError::UnsupportedVersion(version) => {
+                write!(f, "unsupported replay version {}", version)
+            }
+            ReplayError::Truncated => write!(f, "replay is truncated"),
+            ReplayError::BadRunLength => write!(f, "replay has a bad run length"),
+            ReplayError::BadDealing => write!(f, "replay has a bad dealing"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub seed: [u8; 16],
+    /// What the game dealt after the first game.
+    pub dealing: Dealing,
+    /// Each gamepad state and how many frames in a row it lasted.
+    runs: Vec<(Button::Ty, u32)>,
+}
+
+impl Replay {
+    pub fn new(seed: [u8; 16]) -> Self {
+        Replay::with_dealing(seed, Dealing::Random)
+    }
+
+    pub fn with_dealing(seed: [u8; 16], dealing: Dealing) -> Self {
+        Replay {
+            seed,
+            dealing,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Adds a frame with `gamepad` held.
+    pub fn record(&mut self, gamepad: Button::Ty) {
+        if let Some(&mut (last, ref mut frames)) = self.runs.last_mut() {
+            if last == gamepad && *frames < u32::MAX {
+                *frames += 1;
+                return;
+            }
+        }
+
+        self.runs.push((gamepad, 1));
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.runs.iter().map(|&(_, frames)| frames as u64).sum()
+    }
+
+    /// The input for each frame, as `State::frame` would have seen it.
+    pub fn inputs<'a>(&'a self) -> impl Iterator<Item = Input> + 'a {
+        let mut previous_gamepad = Button::Ty::empty();
+
+        self.runs
+            .iter()
+            .flat_map(|&(gamepad, frames)| iter::repeat_n(gamepad, frames as usize))
+            .map(move |gamepad| {
+                let input = Input {
+                    gamepad,
+                    previous_gamepad,
+                };
+                previous_gamepad = gamepad;
+                input
+            })
+    }
+
+    /// Deals the game and calls `step` with each frame's input, which should do what a frontend
+    /// does with it, usually `update_and_render` or just `GameState::update`.
+    pub fn play<F>(&self, mut step: F) -> GameState
+    where
+        F: FnMut(&mut GameState, Input),
+    {
+        // `GameState::solvable` deals again if the first deal can't be won, so it has to be dealt
+        // the same way for the seed to give the same game.
+        let mut state = match self.dealing {
+            Dealing::Solvable(budget) => GameState::solvable(self.seed, None, budget),
+            dealing => {
+                let mut state = GameState::new(self.seed, None);
+                state.dealing = dealing;
+                state
+            }
+        };
+
+        for input in self.inputs() {
+            step(&mut state, input);
+        }
+
+        state
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(REPLAY_MAGIC.len() + 1 + 16 + self.runs.len() * 2);
+
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.push(REPLAY_VERSION);
+        bytes.extend_from_slice(&self.seed);
+
+        match self.dealing {
+            Dealing::Random => bytes.push(0),
+            Dealing::Solvable(budget) => {
+                bytes.push(1);
+                push_number(&mut bytes, budget.nodes as u64);
+                match budget.time {
+                    Some(time) => {
+                        bytes.push(1);
+                        push_number(&mut bytes, time.as_millis() as u64);
+                    }
+                    None => bytes.push(0),
+                }
+            }
+            Dealing::Catalogue(number) => {
+                bytes.push(2);
+                push_number(&mut bytes, number as u64);
+            }
+        }
+
+        for &(gamepad, frames) in self.runs.iter() {
+            bytes.push(gamepad.bits());
+            push_number(&mut bytes, frames as u64);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let header_len = REPLAY_MAGIC.len() + 1 + 16;
+
+        if bytes.len() < REPLAY_MAGIC.len() {
+            return Err(ReplayError::Truncated);
+        }
+        if &bytes[..REPLAY_MAGIC.len()] != REPLAY_MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        if bytes.len() < header_len {
+            return Err(ReplayError::Truncated);
+        }
+
+        let version = bytes[REPLAY_MAGIC.len()];
+        if version != 1 && version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let mut replay = Replay::new([0; 16]);
+        replay
+            .seed
+            .copy_from_slice(&bytes[REPLAY_MAGIC.len() + 1..header_len]);
+
+        let mut rest = bytes[header_len..].iter();
+        if version >= 2 {
+            replay.dealing = read_dealing(&mut rest)?;
+        }
+
+        while let Some(&gamepad) = rest.next() {
+            let frames = read_number(&mut rest, u32::MAX as u64, ReplayError::BadRunLength)?;
+            if frames == 0 {
+                return Err(ReplayError::BadRunLength);
+            }
+
+            replay
+                .runs
+                .push((Button::Ty::from_bits_truncate(gamepad), frames as u32));
+        }
+
+        Ok(replay)
+    }
+}
+
+fn push_number(bytes: &mut Vec<u8>, mut number: u64) {
+    loop {
+        let low = (number & 0x7f) as u8;
+        number >>= 7;
+
+        if number == 0 {
+            bytes.push(low);
+            break;
+        }
+        bytes.push(low | 0x80);
+    }
+}
+
+// Reads a number that should be at most `max`, or fails with `too_big`.
+fn read_number(
+    rest: &mut slice::Iter<u8>,
+    max: u64,
+    too_big: ReplayError,
+) -> Result<u64, ReplayError> {
+    let mut number: u64 = 0;
+    let mut shift = 0;
+
+    loop {
+        let &byte = rest.next().ok_or(ReplayError::Truncated)?;
+
+        if shift >= 64 || (shift == 63 && byte & 0x7f > 1) {
+            return Err(too_big);
+        }
+        number |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    if number > max {
+        return Err(too_big);
+    }
+
+    Ok(number)
+}
+
+fn read_dealing(rest: &mut slice::Iter<u8>) -> Result<Dealing, ReplayError> {
+    let number = |rest: &mut slice::Iter<u8>| {
+        read_number(rest, usize::MAX as u64, ReplayError::BadDealing).map(|number| number as usize)
+    };
+
+    match *rest.next().ok_or(ReplayError::Truncated)? {
+        0 => Ok(Dealing::Random),
+        1 => {
+            let nodes = number(rest)?;
+            let time = match *rest.next().ok_or(ReplayError::Truncated)? {
+                0 => None,
+                1 => Some(Duration::from_millis(number(rest)? as u64)),
+                _ => return Err(ReplayError::BadDealing),
+            };
+
+            Ok(Dealing::Solvable(Budget { nodes, time }))
+        }
+        2 => Ok(Dealing::Catalogue(number(rest)?)),
+        _ => Err(ReplayError::BadDealing),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dealing::{self, CATALOGUE_BUDGET};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use script::script_for_moves;
+    use solver::{solve, Solution};
+
+    const BUTTONS: [Button::Ty; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    #[test]
+    fn playing_a_replay_ends_up_where_the_game_did() {
+        let mut rng = XorShiftRng::from_seed([3; 16]);
+
+        for game in 0..16 {
+            let seed = [game; 16];
+            let mut replay = Replay::new(seed);
+            let mut state = GameState::new(seed, None);
+            let mut input = Input::new();
+
+            for _ in 0..2000 {
+                if rng.gen_range(0, 4) == 0 {
+                    input.gamepad = *rng.choose(&BUTTONS).unwrap();
+                } else if rng.gen() {
+                    input.gamepad = Button::Ty::empty();
+                }
+
+                replay.record(input.gamepad);
+                state.update(input);
+                input.previous_gamepad = input.gamepad;
+            }
+
+            let played = Replay::from_bytes(&replay.to_bytes())
+                .unwrap()
+                .play(|state, input| state.update(input));
+
+            assert_eq!(played.cells, state.cells, "game {}", game);
+            assert_eq!(played.history, state.history);
+            assert_eq!(
+                (played.selectpos, played.selectdepth, played.selectdrop),
+                (state.selectpos, state.selectdepth, state.selectdrop)
+            );
+            assert_eq!(
+                (played.wins, played.movetimer),
+                (state.wins, state.movetimer)
+            );
+        }
+    }
+
+    #[test]
+    fn held_buttons_are_stored_as_runs() {
+        let mut replay = Replay::new([7; 16]);
+        for _ in 0..300 {
+            replay.record(Button::Left);
+        }
+        replay.record(Button::Left | Button::A);
+        replay.record(Button::Ty::empty());
+
+        let bytes = replay.to_bytes();
+        assert_eq!(bytes.len(), 22 + 3 + 2 + 2);
+        assert_eq!(&bytes[22..25], &[Button::Left.bits(), 0xac, 0x02]);
+        assert_eq!(Replay::from_bytes(&bytes), Ok(replay.clone()));
+
+        assert_eq!(replay.frame_count(), 302);
+        let inputs: Vec<_> = replay.inputs().skip(299).collect();
+        assert!(!inputs[0].pressed_this_frame(Button::Left));
+        assert!(inputs[1].pressed_this_frame(Button::A));
+        assert_eq!(inputs[2].previous_gamepad, Button::Left | Button::A);
+    }
+
+    #[test]
+    fn the_dealing_is_kept() {
+        let dealings = [
+            Dealing::Random,
+            Dealing::Catalogue(300),
+            Dealing::Solvable(Budget {
+                nodes: 5_000,
+                time: None,
+            }),
+            Dealing::Solvable(Budget {
+                nodes: 1,
+                time: Some(Duration::from_millis(1500)),
+            }),
+        ];
+
+        for &dealing in dealings.iter() {
+            let mut replay = Replay::with_dealing([9; 16], dealing);
+            replay.record(Button::A);
+
+            assert_eq!(Replay::from_bytes(&replay.to_bytes()), Ok(replay));
+        }
+
+        // Version 1 had no dealing.
+        let mut bytes = Replay::new([9; 16]).to_bytes();
+        bytes[4] = 1;
+        bytes.pop();
+        bytes.extend_from_slice(&[Button::A.bits(), 1]);
+        let mut replay = Replay::new([9; 16]);
+        replay.record(Button::A);
+        assert_eq!(Replay::from_bytes(&bytes), Ok(replay));
+    }
+
+    #[test]
+    fn a_catalogue_game_is_followed_by_the_next_catalogue_deal() {
+        let mut state = GameState::from_catalogue(0, None).unwrap();
+        let mut replay = Replay::with_dealing(dealing::catalogue_seed(0).unwrap(), state.dealing);
+
+        let moves = match solve(state.ruleset.layout, &state.cells, CATALOGUE_BUDGET) {
+            Solution::Solved(moves) => moves,
+            solution => panic!("{:?}", solution),
+        };
+        let mut frames = script_for_moves(&state, &moves).unwrap();
+        // Count the win, and then start the next game.
+        frames.extend_from_slice(&[Button::Ty::empty(), Button::Start, Button::Ty::empty()]);
+
+        let mut input = Input::new();
+        for gamepad in frames {
+            input.previous_gamepad = input.gamepad;
+            input.gamepad = gamepad;
+            replay.record(gamepad);
+            state.update(input);
+        }
+        assert_eq!(state.wins, 1);
+        assert_eq!(state.dealing, Dealing::Catalogue(1));
+
+        let played = Replay::from_bytes(&replay.to_bytes())
+            .unwrap()
+            .play(|state, input| state.update(input));
+
+        assert_eq!(played.cells, state.cells);
+        assert_eq!((played.wins, played.dealing), (state.wins, state.dealing));
+    }
+
+    #[test]
+    fn bad_replays_are_rejected() {
+        let mut bytes = Replay::new([0; 16]).to_bytes();
+        bytes.push(Button::A.bits());
+
+        assert_eq!(Replay::from_bytes(&bytes), Err(ReplayError::Truncated));
+        assert_eq!(
+            Replay::from_bytes(&bytes[..10]),
+            Err(ReplayError::Truncated)
+        );
+        assert_eq!(Replay::from_bytes(b"GIF89a"), Err(ReplayError::BadMagic));
+
+        bytes.push(0);
+        assert_eq!(Replay::from_bytes(&bytes), Err(ReplayError::BadRunLength));
+
+        bytes[21] = 3;
+        assert_eq!(Replay::from_bytes(&bytes), Err(ReplayError::BadDealing));
+
+        bytes[4] = 3;
+        assert_eq!(
+            Replay::from_bytes(&bytes),
+            Err(ReplayError::UnsupportedVersion(3))
+        );
+    }
+}
@@ -0,0 +1,1013 @@
+use inner_common::SPECIAL_BUTTON_COUNT;
+use vm::asm::assemble_with_symbols;
+use {Button, Input, Layout};
+
+/// The bytecode that defines a game, one program per phase of `GameState::update`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ruleset {
+    /// The board the programs were written for. The native code that still works on cells, like
+    /// `MOVE_CARDS`, and the renderer use it as well.
+    pub layout: Layout,
+    /// Run once by `GameState::with_ruleset`, after the cards are dealt.
+    pub initialization: Vec<u8>,
+    /// Run at the start of every update. A non-zero value left on top of the stack means the game
+    /// has been won.
+    pub win_check: Vec<u8>,
+    /// Run whenever the move timer is empty. A non-zero value left on top of the stack means
+    /// something was moved, so the move timer is filled instead of handling input.
+    pub automove: Vec<u8>,
+    pub left: Vec<u8>,
+    pub right: Vec<u8>,
+    pub up: Vec<u8>,
+    pub down: Vec<u8>,
+    pub a: Vec<u8>,
+    pub b: Vec<u8>,
+    /// The buttons in the button column, from the bottom up. The cursor moves over and the screen
+    /// has room for `SPECIAL_BUTTON_COUNT` of them, whether or not there are that many, and any
+    /// more are never shown or pressed.
+    pub special_buttons: Vec<SpecialButton>,
+}
+
+/// A button in the button column, which `HANDLE_BUTTON_PRESS` presses when it is selected. Both
+/// programs start with the index of the button on the stack, and should pop it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecialButton {
+    /// Leaves a non-zero value on top of the stack if the button can be pressed. It is run
+    /// whenever the cards change, and the renderer lights up the buttons it last allowed.
+    pub is_enabled: Vec<u8>,
+    pub on_press: Vec<u8>,
+}
+
+impl Ruleset {
+    /// The rules of Shenzhen Solitaire, played on `layout`.
+    pub fn for_layout(layout: Layout) -> Self {
+        Ruleset {
+            layout,
+            initialization: assemble_program(layout, &[INITIALIZATION]),
+            win_check: assemble_program(layout, &[WIN_CHECK]),
+            automove: assemble_program(layout, &[AUTOMOVE]),
+            left: assemble_program(layout, &[LEFT, CLAMP_SELECT_DEPTH]),
+            right: assemble_program(layout, &[RIGHT, CLAMP_SELECT_DEPTH]),
+            up: assemble_program(layout, &[UP, TOGGLE_ROW]),
+            down: assemble_program(layout, &[DOWN, TOGGLE_ROW]),
+            a: assemble_program(layout, &[A]),
+            b: assemble_program(layout, &[B]),
+            special_buttons: (0..SPECIAL_BUTTON_COUNT)
+                .map(|suit| {
+                    let suit = dragon_suit(suit);
+
+                    SpecialButton {
+                        is_enabled: assemble_program(layout, &[&suit, DRAGONS_CAN_BE_COLLECTED]),
+                        on_press: assemble_program(layout, &[&suit, COLLECT_DRAGONS]),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// The program for the first button that was pressed this frame, if there is one.
+    pub fn button_program(&self, input: Input) -> Option<&[u8]> {
+        let programs = [
+            (Button::Left, &self.left),
+            (Button::Right, &self.right),
+            (Button::Up, &self.up),
+            (Button::Down, &self.down),
+            (Button::A, &self.a),
+            (Button::B, &self.b),
+        ];
+
+        programs
+            .iter()
+            .find(|&&(button, _)| input.pressed_this_frame(button))
+            .map(|&(_, program)| &program[..])
+    }
+}
+
+const INITIALIZATION: &str = "
+    LITERAL START_OF_TABLEAU
+    SET_SELECT_POS
+    LITERAL 1
+    SET_GRAB_POS
+";
+
+// The game is won once every tableau cell is empty.
+const WIN_CHECK: &str = "
+    LITERAL START_OF_TABLEAU
+check_cell:
+    DUP
+    GET_CELL_LEN_AT
+    IF not_won
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL CELLS_MAX_INDEX
+    LE_BRANCH_BACK check_cell
+    FORGET
+    LITERAL TRUE
+    HALT
+not_won:
+    FORGET
+    LITERAL FALSE
+";
+
+// Moves the flower onto its foundation, or the lowest numbered card that every foundation is
+// ready for onto the foundation for its suit, checking the free cells and then the tableau from
+// left to right.
+const AUTOMOVE: &str = "
+    LITERAL 0
+    LITERAL 255
+    LITERAL START_OF_FOUNDATIONS
+min_foundation_num:
+    DUP
+    CALL foundation_num
+    ROT
+    MIN
+    SWAP
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL END_OF_FOUNDATIONS
+    LE_BRANCH_BACK min_foundation_num
+    FORGET
+    LITERAL 1
+    ADD
+check_cell:
+    OVER
+    GET_CELL_LEN_AT
+    IF check_card
+next_cell:
+    SWAP
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL BUTTON_COLUMN
+    NE_BRANCH not_button_column
+    FORGET
+    LITERAL START_OF_TABLEAU
+not_button_column:
+    DUP
+    LITERAL CELLS_MAX_INDEX
+    GT_BRANCH no_move
+    SWAP
+    JUMP_BACK check_cell
+no_move:
+    FORGET_N 2
+    LITERAL FALSE
+    HALT
+check_card:
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    LITERAL FLOWER_CARD
+    EQ_BRANCH move_flower
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    LITERAL CARD_BACK
+    EQ_BRANCH_BACK next_cell
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    GET_CARD_NUM
+    OVER
+    NE_BRANCH_BACK next_cell
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    GET_CARD_SUIT
+    LITERAL START_OF_FOUNDATIONS
+find_suit:
+    OVER
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    GET_CARD_SUIT
+    EQ_BRANCH found_suit
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL END_OF_FOUNDATIONS
+    LE_BRANCH_BACK find_suit
+    FORGET_N 2
+    LITERAL START_OF_FOUNDATIONS
+find_empty:
+    DUP
+    GET_CELL_LEN_AT
+    NOT
+    IF found_empty
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL END_OF_FOUNDATIONS
+    LE_BRANCH_BACK find_empty
+    FORGET
+    JUMP_BACK next_cell
+found_suit:
+    SWAP
+    FORGET
+found_empty:
+    SWAP
+    FORGET
+    JUMP move
+move_flower:
+    FORGET
+    LITERAL FLOWER_FOUNDATION
+move:
+    LITERAL 0
+    SWAP
+    MOVE_CARDS
+    LITERAL TRUE
+    HALT
+foundation_num:
+    DUP
+    GET_CELL_LEN_AT
+    IF foundation_num_top_card
+    FORGET
+    LITERAL 0
+    RET
+foundation_num_top_card:
+    GET_TOP_CARD_AT_OR_255
+    GET_CARD_NUM
+    RET
+";
+
+// The programs for the direction buttons, and the subroutines they share. Each program is
+// assembled with the subroutines it calls appended, so the programs have to end in a `HALT`.
+const CLAMP_SELECT_DEPTH: &str = "
+clamp_select_depth:
+    GET_SELECT_DROP
+    IF clamp_select_depth_dropping
+    GET_CELL_LEN
+    LITERAL 1
+    SUB
+    GET_SELECT_DEPTH
+    LITERAL 0
+    MAX
+    MIN
+    JUMP clamp_select_depth_set
+clamp_select_depth_dropping:
+    LITERAL 0
+clamp_select_depth_set:
+    SET_SELECT_DEPTH
+    RET
+";
+
+// The rows can be different lengths, so the column is clamped to the end of the other row.
+const TOGGLE_ROW: &str = "
+toggle_row:
+    GET_SELECT_POS
+    LITERAL START_OF_TABLEAU
+    GET_SELECT_POS
+    LITERAL END_OF_FOUNDATIONS
+    GT_BRANCH toggle_row_from_tableau
+    ADD
+    LITERAL CELLS_MAX_INDEX
+    MIN
+    JUMP toggle_row_set
+toggle_row_from_tableau:
+    SUB
+    LITERAL END_OF_FOUNDATIONS
+    MIN
+toggle_row_set:
+    SET_SELECT_POS
+    RET
+";
+
+const LEFT: &str = "
+    GET_SELECT_POS
+    LITERAL 0
+    EQ_BRANCH wrap_to_top_row_end
+    GET_SELECT_POS
+    LITERAL START_OF_TABLEAU
+    EQ_BRANCH wrap_to_tableau_end
+    GET_SELECT_POS
+    LITERAL 1
+    SUB
+    JUMP set_pos
+wrap_to_tableau_end:
+    LITERAL CELLS_MAX_INDEX
+    JUMP set_pos
+wrap_to_top_row_end:
+    LITERAL START_OF_TABLEAU - 1
+set_pos:
+    SET_SELECT_POS
+    ASSERT_EMPTY_STACK
+    CALL clamp_select_depth
+    HALT
+";
+
+const RIGHT: &str = "
+    GET_SELECT_POS
+    LITERAL START_OF_TABLEAU - 1
+    EQ_BRANCH wrap_to_top_row_start
+    GET_SELECT_POS
+    LITERAL CELLS_MAX_INDEX
+    GE_BRANCH wrap_to_tableau_start
+    GET_SELECT_POS
+    LITERAL 1
+    ADD
+    JUMP set_pos
+wrap_to_tableau_start:
+    LITERAL START_OF_TABLEAU
+    JUMP set_pos
+wrap_to_top_row_start:
+    LITERAL 0
+set_pos:
+    SET_SELECT_POS
+    ASSERT_EMPTY_STACK
+    CALL clamp_select_depth
+    HALT
+";
+
+const UP: &str = "
+    GET_SELECT_POS
+    LITERAL BUTTON_COLUMN
+    EQ_BRANCH in_button_column
+    GET_CELL_LEN
+    LITERAL 0
+    EQ
+    GET_SELECT_DEPTH
+    GET_CELL_LEN
+    LITERAL 1
+    SUB
+    GE
+    GET_SELECT_DROP
+    OR
+    OR
+    JUMP change_row_if_true
+in_button_column:
+    GET_SELECT_DEPTH
+    LITERAL SPECIAL_BUTTON_COUNT - 1
+    GE
+change_row_if_true:
+    IF change_row
+    GET_SELECT_DEPTH
+    LITERAL 1
+    ADD
+    SET_SELECT_DEPTH
+    HALT
+change_row:
+    CALL toggle_row
+    ASSERT_EMPTY_STACK
+    LITERAL 0
+    SET_SELECT_DEPTH
+    HALT
+";
+
+const DOWN: &str = "
+    GET_SELECT_DEPTH
+    LITERAL 0
+    EQ_BRANCH change_row
+    GET_SELECT_DEPTH
+    LITERAL 1
+    SUB
+    SET_SELECT_DEPTH
+    HALT
+change_row:
+    CALL toggle_row
+    ASSERT_EMPTY_STACK
+    GET_CELL_LEN
+    GET_SELECT_DROP
+    NOT
+    AND
+    IF depth_of_top_card
+    GET_SELECT_POS
+    LITERAL BUTTON_COLUMN
+    EQ_BRANCH bottom_button
+    LITERAL 0
+    JUMP set_depth
+bottom_button:
+    LITERAL SPECIAL_BUTTON_COUNT - 1
+    JUMP set_depth
+depth_of_top_card:
+    GET_CELL_LEN
+    LITERAL 1
+    SUB
+set_depth:
+    SET_SELECT_DEPTH
+    HALT
+";
+
+const A: &str = "
+    GET_SELECT_POS
+    LITERAL BUTTON_COLUMN
+    NE_BRANCH not_button_column
+    HANDLE_BUTTON_PRESS
+    HALT
+not_button_column:
+    GET_SELECT_DROP
+    IF drop
+    CAN_GRAB
+    HALT_UNLESS
+    GET_SELECT_POS
+    SET_GRAB_POS
+    GET_SELECT_DEPTH
+    SET_GRAB_DEPTH
+    GRAB
+    HALT
+drop:
+    GET_GRAB_CARD_OR_HALT
+    GET_SELECT_POS
+    LITERAL BUTTON_COLUMN
+    LT_BRANCH onto_free_cell
+    GET_SELECT_POS
+    LITERAL FLOWER_FOUNDATION
+    GT
+    HALT_UNLESS
+    GET_SELECT_POS
+    LITERAL START_OF_FOUNDATIONS
+    LT
+    GET_SELECT_POS
+    LITERAL START_OF_TABLEAU
+    GE
+    OR
+    IF onto_tableau
+    GET_GRAB_DEPTH
+    NOT
+    HALT_UNLESS
+    GET_CELL_LEN
+    IF onto_foundation_card
+    GET_GRAB_CARD_NUM_OR_255
+    LITERAL 1
+    EQ
+    JUMP check
+onto_foundation_card:
+    GET_DROP_CARD_OR_HALT
+    FORGET
+    GET_GRAB_CARD_SUIT_OR_255
+    GET_DROP_CARD_SUIT_OR_255
+    EQ
+    GET_GRAB_CARD_NUM_OR_255
+    DUP
+    GET_DROP_CARD_NUM_OR_255
+    LITERAL 1
+    ADD
+    EQ
+    AND
+    AND
+    JUMP check
+onto_tableau:
+    GET_DROP_CARD_OR_255
+    LITERAL 255
+    EQ_BRANCH move
+    GET_GRAB_CARD_SUIT_OR_255
+    GET_DROP_CARD_SUIT_OR_255
+    NE
+    GET_GRAB_CARD_NUM_OR_255
+    DUP
+    GET_DROP_CARD_NUM_OR_255
+    LITERAL 1
+    SUB
+    EQ
+    AND
+    AND
+    JUMP check
+onto_free_cell:
+    GET_CELL_LEN
+    NOT
+    GET_GRAB_DEPTH
+    NOT
+    AND
+check:
+    HALT_UNLESS
+move:
+    GET_GRAB_POS
+    GET_GRAB_DEPTH
+    GET_SELECT_POS
+    MOVE_CARDS
+    DROP
+    FILL_MOVE_TIMER
+";
+
+const B: &str = "
+    DROP
+";
+
+// The dragon buttons, which start with `dragon_suit` in front of them. The dragons of suit `n` are
+// the cards numbered `n * FIRST_GREEN_CARD`, and can be collected once all four of them are on top
+// of a cell, and there is a free cell that is empty or holds one of them.
+const DRAGONS_CAN_BE_COLLECTED: &str = "
+    LITERAL FIRST_GREEN_CARD
+    MUL
+    LITERAL 0
+    SWAP
+    LITERAL 0
+count_dragons:
+    OVER
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    NE_BRANCH next_cell
+    ROT
+    LITERAL 1
+    ADD
+    ROT
+    ROT
+next_cell:
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL CELLS_MAX_INDEX
+    LE_BRANCH_BACK count_dragons
+    FORGET
+    SWAP
+    LITERAL 4
+    LT_BRANCH disabled
+    LITERAL 0
+find_free_cell:
+    DUP
+    GET_CELL_LEN_AT
+    NOT
+    IF enabled
+    OVER
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    EQ_BRANCH enabled
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL BUTTON_COLUMN
+    LT_BRANCH_BACK find_free_cell
+    FORGET
+disabled:
+    FORGET
+    LITERAL FALSE
+    HALT
+enabled:
+    FORGET_N 2
+    LITERAL TRUE
+";
+
+// Replaces the dragons with a face down card, in the free cell that already has one of them on top
+// if there is one, or else the first empty free cell.
+const COLLECT_DRAGONS: &str = "
+    LITERAL FIRST_GREEN_CARD
+    MUL
+    LITERAL 0
+find_dragon_cell:
+    OVER
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    EQ_BRANCH found_cell
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL BUTTON_COLUMN
+    LT_BRANCH_BACK find_dragon_cell
+    FORGET
+    LITERAL 0
+find_empty_cell:
+    DUP
+    GET_CELL_LEN_AT
+    NOT
+    IF found_cell
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL BUTTON_COLUMN
+    LT_BRANCH_BACK find_empty_cell
+found_cell:
+    SWAP
+    LITERAL 0
+discard_dragons:
+    OVER
+    OVER
+    GET_TOP_CARD_AT_OR_255
+    NE_BRANCH next_cell
+    DUP
+    DISCARD_CARD_AT
+next_cell:
+    LITERAL 1
+    ADD
+    DUP
+    LITERAL CELLS_MAX_INDEX
+    LE_BRANCH_BACK discard_dragons
+    FORGET_N 2
+    DUP
+    LITERAL BUTTON_COLUMN
+    EQ_BRANCH no_free_cell
+    LITERAL CARD_BACK
+    SWAP
+    PUT_CARD_AT
+    JUMP done
+no_free_cell:
+    FORGET
+done:
+    DROP
+    FILL_MOVE_TIMER
+";
+
+// Swaps the index the button was pressed at for the suit of dragons it collects, so each button
+// says which suit it is for.
+fn dragon_suit(suit: u8) -> String {
+    format!("\n    FORGET\n    LITERAL {}\n", suit)
+}
+
+// The sources are fixed, and every `Layout` has all the cells they refer to, so failing to
+// assemble them is a bug in this file.
+fn assemble_program(layout: Layout, sources: &[&str]) -> Vec<u8> {
+    let source = sources.concat();
+
+    match assemble_with_symbols(&source, &layout.symbols()) {
+        Ok(bytecode) => bytecode,
+        Err(error) => panic!("{}\n{}", error, source),
+    }
+}
+
+impl Default for Ruleset {
+    /// The rules of Shenzhen Solitaire.
+    fn default() -> Self {
+        Ruleset::for_layout(Layout::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inner_common::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::sync::Arc;
+    use vm::asm::assemble;
+    use vm::instructions::HANDLE_BUTTON_PRESS;
+    use vm::verify::{verify, verify_with_arguments};
+    use {automove, candrop, cangrab, canmovedragons, haswon, movecards, movedragons};
+
+    fn pressed(button: Button::Ty) -> Input {
+        Input {
+            gamepad: button,
+            previous_gamepad: Button::Ty::empty(),
+        }
+    }
+
+    fn deal(ruleset: &Arc<Ruleset>, seed: u16) -> GameState {
+        let mut seed_bytes = [0; 16];
+        seed_bytes[0] = seed as u8;
+        seed_bytes[1] = (seed >> 8) as u8;
+
+        GameState::with_ruleset(seed_bytes, None, Arc::clone(ruleset))
+    }
+
+    // The default layout, and ones with fewer free cells, more foundations and a shorter tableau.
+    fn rulesets() -> Vec<Arc<Ruleset>> {
+        [
+            Layout::default(),
+            Layout::new(2, 3, 6).unwrap(),
+            Layout::new(1, 4, 8).unwrap(),
+        ]
+        .iter()
+        .map(|&layout| Arc::new(Ruleset::for_layout(layout)))
+        .collect()
+    }
+
+    #[test]
+    fn the_programs_pass_the_verifier() {
+        for ruleset in rulesets() {
+            verify_ruleset(&ruleset);
+        }
+    }
+
+    fn verify_ruleset(ruleset: &Ruleset) {
+        for program in [
+            &ruleset.initialization,
+            &ruleset.win_check,
+            &ruleset.automove,
+            &ruleset.left,
+            &ruleset.right,
+            &ruleset.up,
+            &ruleset.down,
+            &ruleset.a,
+            &ruleset.b,
+        ]
+        .iter()
+        {
+            let report = verify(program);
+            assert!(
+                report.is_ok(),
+                "{:?} {:?}",
+                ruleset.layout,
+                report.violations
+            );
+        }
+
+        for button in ruleset.special_buttons.iter() {
+            for program in [&button.is_enabled, &button.on_press].iter() {
+                let report = verify_with_arguments(program, 1);
+                assert!(
+                    report.is_ok(),
+                    "{:?} {:?}",
+                    ruleset.layout,
+                    report.violations
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn the_win_check_matches_haswon() {
+        let mut wins = 0;
+
+        for ruleset in rulesets() {
+            let layout = ruleset.layout;
+
+            for seed in 0..1024u16 {
+                let mut state = deal(&ruleset, seed);
+
+                // Empty each tableau cell depending on one bit of the seed, so every combination
+                // of empty and non-empty cells comes up.
+                for i in layout.start_of_tableau()..=layout.cells_max_index() {
+                    if seed & (1 << (i - layout.start_of_tableau())) != 0 {
+                        state.cells[i as usize].clear();
+                    }
+                }
+                if seed & 0x100 != 0 {
+                    state.cells[0].push(FLOWER_CARD);
+                }
+
+                let expected = haswon(layout, &state.cells);
+                if expected {
+                    wins += 1;
+                }
+
+                assert_eq!(state.has_won(), expected, "{:?} seed {}", layout, seed);
+                assert!(state.vm.is_empty());
+            }
+        }
+
+        // Every tableau cell is empty for 4 seeds out of 1024 with 8 columns, and 16 with 6.
+        assert_eq!(wins, 4 + 16 + 4);
+    }
+
+    #[test]
+    fn the_automove_program_matches_automove() {
+        let mut rng = XorShiftRng::from_seed([1; 16]);
+        let mut moves = 0;
+
+        for ruleset in rulesets() {
+            let cell_count = ruleset.layout.cells_max_index() + 1;
+
+            for seed in 0..1024u16 {
+                let mut state = deal(&ruleset, seed);
+
+                for _ in 0..64 {
+                    let mut expected = state.clone();
+                    let expected_moved = automove(ruleset.layout, &mut expected.cells);
+
+                    assert_eq!(state.try_automove(), expected_moved, "seed {}", seed);
+                    assert_eq!(state.cells, expected.cells, "seed {}", seed);
+
+                    if expected_moved {
+                        moves += 1;
+                        continue;
+                    }
+
+                    // Get to states a real game might not, with a move that ignores the rules,
+                    // and put some dragons away so face down cards come up too.
+                    let suit = rng.gen_range(0, 3);
+                    if canmovedragons(ruleset.layout, &state.cells, suit) {
+                        movedragons(ruleset.layout, &mut state.cells, suit);
+                    } else {
+                        let grabpos = rng.gen_range(0, cell_count);
+                        let droppos = rng.gen_range(0, cell_count);
+                        movecards(ruleset.layout, &mut state.cells, grabpos, 0, droppos);
+                    }
+                }
+            }
+        }
+
+        assert!(moves > 5000, "only {} moves", moves);
+    }
+
+    #[test]
+    fn the_dragon_buttons_match_canmovedragons_and_movedragons() {
+        let mut rng = XorShiftRng::from_seed([2; 16]);
+        let mut presses = 0;
+
+        for ruleset in rulesets() {
+            let cell_count = ruleset.layout.cells_max_index() + 1;
+
+            for seed in 0..512u16 {
+                let mut state = deal(&ruleset, seed);
+
+                for _ in 0..64 {
+                    for suit in 0..4 {
+                        let enabled = canmovedragons(ruleset.layout, &state.cells, suit);
+                        assert_eq!(state.special_button_enabled(suit), enabled, "seed {}", seed);
+
+                        if !enabled {
+                            continue;
+                        }
+
+                        let mut expected = state.clone();
+                        movedragons(ruleset.layout, &mut expected.cells, suit);
+                        expected.selectdrop = false;
+                        expected.movetimer = MOVE_TIMER_MAX;
+
+                        let mut pressed = state.clone();
+                        pressed.selectdepth = suit;
+                        pressed.interpret(&[HANDLE_BUTTON_PRESS]).unwrap();
+
+                        assert_eq!(pressed.cells, expected.cells, "seed {}", seed);
+                        assert_eq!(pressed.selectdrop, expected.selectdrop);
+                        assert_eq!(pressed.movetimer, expected.movetimer);
+                        assert!(pressed.vm.is_empty());
+                        presses += 1;
+                    }
+
+                    // Shuffle cards around without regard for the rules, to line the dragons up
+                    // more often than a real game would.
+                    let grabpos = rng.gen_range(0, cell_count);
+                    let droppos = rng.gen_range(0, cell_count);
+                    movecards(ruleset.layout, &mut state.cells, grabpos, 0, droppos);
+                    if rng.gen_range(0, 16) == 0 {
+                        let suit = rng.gen_range(0, 3);
+                        if canmovedragons(ruleset.layout, &state.cells, suit) {
+                            movedragons(ruleset.layout, &mut state.cells, suit);
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(presses > 100, "only {} presses", presses);
+    }
+
+    #[test]
+    fn smaller_layouts_are_dealt_and_navigated_within_their_cells() {
+        let layout = Layout::new(2, 3, 6).unwrap();
+        let mut state =
+            GameState::with_ruleset([0; 16], None, Arc::new(Ruleset::for_layout(layout)));
+
+        let lens: Vec<_> = state.cells.iter().map(|cell| cell.len()).collect();
+        assert_eq!(lens, vec![0, 0, 0, 0, 0, 0, 0, 7, 7, 7, 7, 6, 6, 0, 0, 0]);
+        assert_eq!(state.selectpos, 7);
+
+        let ruleset = Arc::clone(&state.ruleset);
+        let press = |state: &mut GameState, program: &[u8]| {
+            state.interpret(program).unwrap();
+            state.vm.clear();
+            state.selectpos
+        };
+
+        assert_eq!(press(&mut state, &ruleset.left), 12);
+        assert_eq!(press(&mut state, &ruleset.right), 7);
+        assert_eq!(press(&mut state, &ruleset.left), 12);
+
+        // The top row is a cell longer than the tableau, so its last cell is above the last column.
+        state.selectdepth = 5;
+        assert_eq!(press(&mut state, &ruleset.up), 5);
+        assert_eq!(press(&mut state, &ruleset.right), 6);
+        assert_eq!(press(&mut state, &ruleset.down), 12);
+        assert_eq!(press(&mut state, &ruleset.right), 7);
+
+        // Each row wraps around on itself.
+        let rows = [
+            (0, layout.end_of_foundations()),
+            (layout.start_of_tableau(), layout.cells_max_index()),
+        ];
+        for &(start, end) in rows.iter() {
+            state.selectpos = start;
+            let mut visited = vec![];
+            for _ in start..=end {
+                visited.push(press(&mut state, &ruleset.right));
+            }
+            visited.sort();
+            assert_eq!(visited, (start..=end).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn the_a_program_drops_where_candrop_says_it_can() {
+        let mut rng = XorShiftRng::from_seed([4; 16]);
+        let mut drops = 0;
+
+        for ruleset in rulesets() {
+            let layout = ruleset.layout;
+            let cell_count = layout.cells_max_index() + 1;
+
+            for seed in 0..64u16 {
+                let mut state = deal(&ruleset, seed);
+
+                for _ in 0..32 {
+                    for grabpos in 0..cell_count {
+                        for grabdepth in 0..state.cells[grabpos as usize].len() as u8 {
+                            if !cangrab(layout, &state.cells, grabpos, grabdepth) {
+                                continue;
+                            }
+
+                            for droppos in 0..cell_count {
+                                if droppos == layout.button_column() {
+                                    continue;
+                                }
+
+                                let mut dropped = state.clone();
+                                dropped.selectdrop = true;
+                                dropped.grabpos = grabpos;
+                                dropped.grabdepth = grabdepth;
+                                dropped.selectpos = droppos;
+                                dropped.movetimer = 0;
+                                dropped.interpret(&ruleset.a).unwrap();
+
+                                let expected =
+                                    candrop(layout, &state.cells, grabpos, grabdepth, droppos);
+                                assert_eq!(
+                                    dropped.movetimer == MOVE_TIMER_MAX,
+                                    expected,
+                                    "{:?} {} {} {}",
+                                    state.cells,
+                                    grabpos,
+                                    grabdepth,
+                                    droppos
+                                );
+
+                                if expected {
+                                    let mut cells = state.cells.clone();
+                                    movecards(layout, &mut cells, grabpos, grabdepth, droppos);
+                                    assert_eq!(dropped.cells, cells);
+                                    drops += 1;
+                                }
+                            }
+                        }
+                    }
+
+                    let grabpos = rng.gen_range(0, cell_count);
+                    let droppos = rng.gen_range(0, cell_count);
+                    movecards(layout, &mut state.cells, grabpos, 0, droppos);
+                }
+            }
+        }
+
+        assert!(drops > 1000, "only {} drops", drops);
+    }
+
+    #[test]
+    fn only_a_one_can_be_dropped_onto_an_empty_foundation() {
+        for (card, expected) in [(1, vec![1]), (3, vec![])] {
+            let mut state = GameState::new([0; 16], None);
+            for cell in state.cells.iter_mut() {
+                cell.clear();
+            }
+            state.cells[0].push(12);
+            state.cells[8].push(card);
+            state.grabpos = 8;
+            state.grabdepth = 0;
+            state.selectpos = 5;
+            state.selectdepth = 0;
+            state.selectdrop = true;
+
+            let a = state.ruleset.a.clone();
+            state.interpret(&a).unwrap();
+
+            assert_eq!(state.cells[5], expected, "card {}", card);
+            assert_eq!(state.cells[0], vec![12]);
+        }
+    }
+
+    #[test]
+    fn update_runs_the_programs_of_the_ruleset() {
+        let ruleset = Ruleset {
+            initialization: assemble("LITERAL 2\nSET_SELECT_POS").unwrap(),
+            automove: vec![],
+            left: assemble("LITERAL 3\nSET_SELECT_POS").unwrap(),
+            ..Ruleset::default()
+        };
+        let mut state = GameState::with_ruleset([0; 16], None, Arc::new(ruleset));
+        assert_eq!(state.selectpos, 2);
+
+        state.update(pressed(Button::Left));
+        assert_eq!(state.selectpos, 3);
+
+        state.update(pressed(Button::Right));
+        assert_eq!(state.selectpos, 4);
+    }
+
+    #[test]
+    fn update_counts_a_win_once() {
+        let ruleset = Ruleset {
+            win_check: assemble("LITERAL TRUE").unwrap(),
+            ..Ruleset::default()
+        };
+        let mut state = GameState::with_ruleset([0; 16], None, Arc::new(ruleset));
+
+        state.update(Input::new());
+        state.update(Input::new());
+        assert_eq!(state.wins, 1);
+
+        state.update(pressed(Button::Start));
+        assert_eq!(state.wins, 1);
+        assert!(!state.win_done);
+        assert_eq!(state.ruleset.win_check, assemble("LITERAL TRUE").unwrap());
+    }
+
+    #[test]
+    fn update_works_out_which_buttons_are_enabled_when_the_cards_change() {
+        let mut state = GameState::new([0; 16], None);
+        for cell in state.cells.iter_mut() {
+            cell.clear();
+        }
+        for pos in 8..12 {
+            state.cells[pos].push(FIRST_GREEN_CARD);
+        }
+        state.update_enabled_buttons();
+        assert_eq!(state.enabled_buttons, vec![false, true, false]);
+
+        state.selectpos = state.ruleset.layout.button_column();
+        state.selectdepth = 1;
+        state.update(pressed(Button::A));
+        assert_eq!(state.cells[0], vec![CARD_BACK]);
+        assert_eq!(state.enabled_buttons, vec![false, false, false]);
+    }
+}
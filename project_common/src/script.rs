@@ -0,0 +1,337 @@
+// A script says what to do with the gamepad on each frame, for playing the game without anyone at
+// the controls. Each line is one of:
+//
+//     A+Left 2      press the buttons, joined with `+`, for a frame and let go for a frame, this
+//                   many times, or once if the count is left out
+//     hold Up 30    hold the buttons down for this many frames
+//     wait 60       hold nothing down for this many frames
+//
+// Button names are the ones in `Button`, in any case. Anything after a `#` is a comment. Moves
+// take `MOVE_TIMER_MAX` frames, and the game ignores the buttons until they are done, so a script
+// has to wait after each one.
+//
+// The frames of a script can also be made from a list of moves, like the ones the solver finds.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::iter;
+
+use moves::Move;
+use {Button, GameState, Input};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    UnknownButton {
+        line: usize,
+        name: String,
+    },
+    BadCount {
+        line: usize,
+        count: String,
+    },
+    /// `hold` or `wait` without a count.
+    MissingCount {
+        line: usize,
+    },
+    /// More on the line after the count.
+    TrailingText {
+        line: usize,
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScriptError::UnknownButton { line, ref name } => {
+                write!(f, "line {}: unknown button `{}`", line, name)
+            }
+            ScriptError::BadCount { line, ref count } => {
+                write!(f, "line {}: bad count `{}`", line, count)
+            }
+            ScriptError::MissingCount { line } => write!(f, "line {}: missing count", line),
+            ScriptError::TrailingText { line } => {
+                write!(f, "line {}: unexpected text after the count", line)
+            }
+        }
+    }
+}
+
+/// The gamepad on each frame of the script.
+pub fn parse_script(script: &str) -> Result<Vec<Button::Ty>, ScriptError> {
+    let mut frames = Vec::new();
+
+    for (index, line) in script.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+
+        let first = match words.next() {
+            Some(word) => word,
+            None => continue,
+        };
+
+        let count = |word: Option<&str>| match word {
+            Some(count) => count.parse::<usize>().map_err(|_| ScriptError::BadCount {
+                line: line_number,
+                count: count.to_owned(),
+            }),
+            None => Err(ScriptError::MissingCount { line: line_number }),
+        };
+
+        match first.to_lowercase().as_str() {
+            "wait" => {
+                let count = count(words.next())?;
+                frames.extend(iter::repeat_n(Button::Ty::empty(), count));
+            }
+            "hold" => {
+                let buttons = parse_buttons(words.next().unwrap_or(""), line_number)?;
+                let count = count(words.next())?;
+                frames.extend(iter::repeat_n(buttons, count));
+            }
+            _ => {
+                let buttons = parse_buttons(first, line_number)?;
+                let count = match words.next() {
+                    Some(word) => count(Some(word))?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    frames.push(buttons);
+                    frames.push(Button::Ty::empty());
+                }
+            }
+        }
+
+        if words.next().is_some() {
+            return Err(ScriptError::TrailingText { line: line_number });
+        }
+    }
+
+    Ok(frames)
+}
+
+fn parse_buttons(names: &str, line: usize) -> Result<Button::Ty, ScriptError> {
+    let mut buttons = Button::Ty::empty();
+
+    for name in names.split('+') {
+        buttons.insert(match name.to_lowercase().as_str() {
+            "a" => Button::A,
+            "b" => Button::B,
+            "select" => Button::Select,
+            "start" => Button::Start,
+            "up" => Button::Up,
+            "down" => Button::Down,
+            "left" => Button::Left,
+            "right" => Button::Right,
+            _ => {
+                return Err(ScriptError::UnknownButton {
+                    line,
+                    name: name.to_owned(),
+                })
+            }
+        });
+    }
+
+    Ok(buttons)
+}
+
+/// The gamepad on each frame of a script that makes `moves`, starting from `state`. The cursor is
+/// moved and A is pressed like a player would, and the script waits for the automoves after each
+/// move. The error is the first move that could not be made that way.
+pub fn script_for_moves(state: &GameState, moves: &[Move]) -> Result<Vec<Button::Ty>, Move> {
+    let mut state = state.clone();
+    let layout = state.ruleset.layout;
+    let mut frames = Vec::new();
+
+    wait_for_automoves(&mut state, &mut frames);
+    if state.selectdrop {
+        press(&mut state, &mut frames, Button::B);
+    }
+
+    for &move_ in moves {
+        let mut expected = state.cells.clone();
+        move_.apply(layout, &mut expected);
+
+        let made = match move_ {
+            Move::Cards {
+                grabpos,
+                depth,
+                droppos,
+            } => {
+                select(&mut state, &mut frames, |state| {
+                    state.selectpos == grabpos && state.selectdepth == depth
+                }) && {
+                    press(&mut state, &mut frames, Button::A);
+                    state.selectdrop
+                } && select(&mut state, &mut frames, |state| state.selectpos == droppos)
+            }
+            Move::Dragons { suit } => select(&mut state, &mut frames, |state| {
+                state.selectpos == layout.button_column() && state.selectdepth == suit
+            }),
+        };
+        if !made {
+            return Err(move_);
+        }
+
+        press(&mut state, &mut frames, Button::A);
+        if state.cells != expected {
+            return Err(move_);
+        }
+
+        wait_for_automoves(&mut state, &mut frames);
+    }
+
+    Ok(frames)
+}
+
+fn step(state: &mut GameState, frames: &mut Vec<Button::Ty>, gamepad: Button::Ty) {
+    state.update(Input {
+        gamepad,
+        previous_gamepad: frames.last().cloned().unwrap_or_default(),
+    });
+    frames.push(gamepad);
+}
+
+fn press(state: &mut GameState, frames: &mut Vec<Button::Ty>, button: Button::Ty) {
+    step(state, frames, button);
+    step(state, frames, Button::Ty::empty());
+}
+
+fn wait_for_automoves(state: &mut GameState, frames: &mut Vec<Button::Ty>) {
+    loop {
+        let before = state.cells.clone();
+        step(state, frames, Button::Ty::empty());
+
+        if (state.movetimer == 0 && state.cells == before) || state.has_won() {
+            break;
+        }
+    }
+}
+
+// Moves the cursor with the fewest presses of the arrow buttons that gets it somewhere `is_target`
+// is true, if there is such a place.
+fn select<F>(state: &mut GameState, frames: &mut Vec<Button::Ty>, is_target: F) -> bool
+where
+    F: Fn(&GameState) -> bool,
+{
+    let arrows = [Button::Left, Button::Right, Button::Up, Button::Down];
+
+    let mut seen = HashSet::new();
+    seen.insert((state.selectpos, state.selectdepth));
+    let mut queue = VecDeque::new();
+    queue.push_back((state.clone(), Vec::new()));
+
+    while let Some((candidate, presses)) = queue.pop_front() {
+        if is_target(&candidate) {
+            for &button in presses.iter() {
+                press(state, frames, button);
+            }
+            return true;
+        }
+
+        for &button in arrows.iter() {
+            let mut next = candidate.clone();
+            next.update(Input {
+                gamepad: button,
+                previous_gamepad: Button::Ty::empty(),
+            });
+
+            if seen.insert((next.selectpos, next.selectdepth)) {
+                let mut presses = presses.clone();
+                presses.push(button);
+                queue.push_back((next, presses));
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dealing::CATALOGUE_BUDGET;
+    use solver::{solve, Solution};
+
+    #[test]
+    fn each_kind_of_line_becomes_frames() {
+        let script = "
+            # Grab the top card of the first column.
+            a
+            Up+Left 2  # twice
+            hold Right 3
+            wait 2
+        ";
+        let none = Button::Ty::empty();
+
+        assert_eq!(
+            parse_script(script),
+            Ok(vec![
+                Button::A,
+                none,
+                Button::Up | Button::Left,
+                none,
+                Button::Up | Button::Left,
+                none,
+                Button::Right,
+                Button::Right,
+                Button::Right,
+                none,
+                none,
+            ])
+        );
+    }
+
+    #[test]
+    fn the_solvers_moves_are_made_with_the_buttons() {
+        let mut state = GameState::from_catalogue(0, None).unwrap();
+        let layout = state.ruleset.layout;
+        let moves = match solve(layout, &state.cells, CATALOGUE_BUDGET) {
+            Solution::Solved(moves) => moves,
+            solution => panic!("{:?}", solution),
+        };
+
+        let frames = script_for_moves(&state, &moves).unwrap();
+
+        let mut input = Input::new();
+        for gamepad in frames {
+            input.previous_gamepad = input.gamepad;
+            input.gamepad = gamepad;
+            state.update(input);
+        }
+        assert!(state.has_won());
+
+        let state = GameState::from_catalogue(0, None).unwrap();
+        let illegal = Move::Cards {
+            grabpos: layout.button_column() + 1,
+            depth: 0,
+            droppos: 0,
+        };
+        assert_eq!(script_for_moves(&state, &[illegal]), Err(illegal));
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        assert_eq!(
+            parse_script("A\nA+C"),
+            Err(ScriptError::UnknownButton {
+                line: 2,
+                name: "C".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_script("wait"),
+            Err(ScriptError::MissingCount { line: 1 })
+        );
+        assert_eq!(
+            parse_script("\nhold B x"),
+            Err(ScriptError::BadCount {
+                line: 2,
+                count: "x".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_script("wait 1 2"),
+            Err(ScriptError::TrailingText { line: 1 })
+        );
+    }
+}
@@ -0,0 +1,393 @@
+// A best first search over the moves a player can make, where each move is followed by the
+// automoves the game would make after it. Positions that only differ in which free cell,
+// foundation or tableau column holds what are treated as the same position, and each position is
+// only searched once.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use inner_common::*;
+use moves::{legal_moves, Move};
+use {automove, getcardnum, getsuit, haswon, Layout};
+
+/// How much searching to do before giving up. The time limit is left out by default, since
+/// `Instant` is not available on every target the game runs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    /// How many positions to search.
+    pub nodes: usize,
+    pub time: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            nodes: 200_000,
+            time: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Solution {
+    /// The moves that win the game, not counting the automoves after each of them.
+    Solved(Vec<Move>),
+    /// Every position that can be reached was searched, and none of them are won.
+    Unsolvable,
+    /// The budget ran out first.
+    Unknown,
+}
+
+/// Makes every automove there is to make, like the game does before it takes any input.
+pub fn settle(layout: Layout, cells: &mut Cells) {
+    while automove(layout, cells) {}
+}
+
+pub fn solve(layout: Layout, cells: &Cells, budget: Budget) -> Solution {
+    // The positions that have been reached, and the move from the position before each of them.
+    struct Node {
+        parent: usize,
+        via: Option<Move>,
+    }
+
+    let deadline = budget.time.map(|time| Instant::now() + time);
+
+    let mut start = cells.clone();
+    settle(layout, &mut start);
+    if haswon(layout, &start) {
+        return Solution::Solved(Vec::new());
+    }
+
+    let mut seen = HashSet::new();
+    seen.insert(canonical(layout, &start));
+
+    let mut nodes = vec![Node {
+        parent: 0,
+        via: None,
+    }];
+    let mut open = BinaryHeap::new();
+    open.push((Reverse((estimate(layout, &start), 0)), 0, start));
+
+    while let Some((Reverse((_, moves_so_far)), index, cells)) = open.pop() {
+        for move_ in candidate_moves(layout, &cells) {
+            let mut next = cells.clone();
+            move_.apply(layout, &mut next);
+            settle(layout, &mut next);
+
+            if !seen.insert(canonical(layout, &next)) {
+                continue;
+            }
+
+            nodes.push(Node {
+                parent: index,
+                via: Some(move_),
+            });
+
+            if haswon(layout, &next) {
+                let mut moves = Vec::new();
+                let mut index = nodes.len() - 1;
+                while let Some(move_) = nodes[index].via {
+                    moves.push(move_);
+                    index = nodes[index].parent;
+                }
+                moves.reverse();
+
+                return Solution::Solved(moves);
+            }
+
+            if nodes.len() >= budget.nodes {
+                return Solution::Unknown;
+            }
+            if let Some(deadline) = deadline {
+                if nodes.len() % 1024 == 0 && Instant::now() >= deadline {
+                    return Solution::Unknown;
+                }
+            }
+
+            let score = estimate(layout, &next) + moves_so_far + 1;
+            open.push((Reverse((score, moves_so_far + 1)), nodes.len() - 1, next));
+        }
+    }
+
+    Solution::Unsolvable
+}
+
+// Roughly how far a position is from being won, for deciding what to search first. Every card
+// still to be put away counts, and so does every card on top of a lower card of the same suit, or
+// in a free cell.
+fn estimate(layout: Layout, cells: &Cells) -> usize {
+    let mut estimate = 0;
+
+    for pos in 0..layout.button_column() {
+        if let Some(&card) = cells[pos as usize].last() {
+            if card != CARD_BACK {
+                estimate += 2;
+            }
+        }
+    }
+
+    for pos in layout.start_of_tableau()..=layout.cells_max_index() {
+        let column = &cells[pos as usize];
+
+        for (i, &card) in column.iter().enumerate() {
+            estimate += 1;
+
+            let buried = column[..i].iter().any(|&below| {
+                getsuit(below) == getsuit(card)
+                    && getcardnum(below) != 0
+                    && getcardnum(below) < getcardnum(card)
+            });
+            if buried {
+                estimate += 1;
+            }
+        }
+    }
+
+    estimate * 3
+}
+
+/// The first move of a winning line, if one can be found within `budget`, or else the move the
+/// search would try first. `None` if there are no moves to make, or the game is already won.
+pub fn hint(layout: Layout, cells: &Cells, budget: Budget) -> Option<Move> {
+    match solve(layout, cells, budget) {
+        Solution::Solved(moves) => moves.first().cloned(),
+        Solution::Unsolvable | Solution::Unknown => {
+            let mut cells = cells.clone();
+            settle(layout, &mut cells);
+
+            candidate_moves(layout, &cells).first().cloned()
+        }
+    }
+}
+
+// The legal moves worth trying, best first. Moves that can only lead to a position that is the
+// same as this one up to `canonical` are left out.
+fn candidate_moves(layout: Layout, cells: &Cells) -> Vec<Move> {
+    let free_cells = 0..layout.button_column();
+    let first_empty = |mut range: Range<u8>| range.find(|&pos| cells[pos as usize].is_empty());
+    let empty_free_cell = first_empty(free_cells.clone());
+    let empty_column = first_empty(layout.start_of_tableau()..layout.cells_max_index() + 1);
+
+    let priority = |move_: &Move| match *move_ {
+        Move::Dragons { .. } => Some(0),
+        Move::Cards {
+            grabpos,
+            depth,
+            droppos,
+        } => {
+            if droppos == grabpos {
+                None
+            } else if droppos < layout.button_column() {
+                if free_cells.contains(&grabpos) || Some(droppos) != empty_free_cell {
+                    None
+                } else {
+                    Some(4)
+                }
+            } else if droppos <= layout.end_of_foundations() {
+                Some(1)
+            } else if cells[droppos as usize].is_empty() {
+                let whole_column = depth as usize + 1 == cells[grabpos as usize].len()
+                    && layout.is_tableau(grabpos);
+                if whole_column || Some(droppos) != empty_column {
+                    None
+                } else {
+                    Some(3)
+                }
+            } else {
+                Some(2)
+            }
+        }
+    };
+
+    let mut moves: Vec<(u8, Move)> = legal_moves(layout, cells)
+        .into_iter()
+        .filter_map(|move_| priority(&move_).map(|priority| (priority, move_)))
+        .collect();
+
+    moves.sort_by_key(|&(priority, _)| priority);
+
+    moves.into_iter().map(|(_, move_)| move_).collect()
+}
+
+// The position with the free cells, the foundations and the tableau columns each sorted.
+fn canonical(layout: Layout, cells: &Cells) -> Vec<u8> {
+    let top = |range: Range<u8>| {
+        let mut cards: Vec<u8> = range
+            .map(|pos| cells[pos as usize].last().cloned().unwrap_or(255))
+            .collect();
+        cards.sort();
+        cards
+    };
+
+    let mut key = Vec::with_capacity(64);
+    key.extend(top(0..layout.button_column()));
+    key.push(cells[layout.flower_foundation() as usize].len() as u8);
+    key.extend(top(layout.start_of_foundations()..layout.start_of_tableau()));
+
+    let mut columns: Vec<&Vec<u8>> = (layout.start_of_tableau()..=layout.cells_max_index())
+        .map(|pos| &cells[pos as usize])
+        .collect();
+    columns.sort();
+    for column in columns {
+        key.extend(column.iter().cloned());
+        key.push(255);
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ruleset::Ruleset;
+    use std::sync::Arc;
+    use {Button, GameState, Input};
+
+    fn press(state: &mut GameState, button: Button::Ty) {
+        state.update(Input {
+            gamepad: button,
+            previous_gamepad: Button::Ty::empty(),
+        });
+    }
+
+    // Lets the move timer run out and any automoves happen.
+    fn wait(state: &mut GameState) {
+        for _ in 0..(MOVE_TIMER_MAX as usize + 1) * 52 {
+            state.update(Input::new());
+        }
+    }
+
+    fn play(layout: Layout, cells: &Cells, moves: &[Move]) -> Cells {
+        let mut cells = cells.clone();
+        settle(layout, &mut cells);
+
+        for move_ in moves {
+            assert!(legal_moves(layout, &cells).contains(move_), "{:?}", move_);
+
+            move_.apply(layout, &mut cells);
+            settle(layout, &mut cells);
+        }
+
+        cells
+    }
+
+    #[test]
+    fn solutions_win_the_game() {
+        let mut solved = 0;
+
+        for seed in 0..16 {
+            let state = GameState::new([seed; 16], None);
+            let layout = state.ruleset.layout;
+
+            if let Solution::Solved(moves) = solve(layout, &state.cells, Budget::default()) {
+                assert!(haswon(layout, &play(layout, &state.cells, &moves)));
+                solved += 1;
+            }
+        }
+
+        assert!(solved >= 12, "only solved {}", solved);
+    }
+
+    #[test]
+    fn positions_with_no_way_to_win_are_unsolvable() {
+        let layout = Layout::new(1, 3, 2).unwrap();
+        let mut cells: Cells = Default::default();
+        cells[layout.start_of_tableau() as usize] = vec![1, 13];
+        cells[layout.cells_max_index() as usize] = vec![11, 23];
+
+        assert_eq!(
+            solve(layout, &cells, Budget::default()),
+            Solution::Unsolvable
+        );
+    }
+
+    #[test]
+    fn running_out_of_budget_is_unknown() {
+        let state = GameState::new([0; 16], None);
+        let budget = Budget {
+            nodes: 1,
+            ..Budget::default()
+        };
+
+        assert_eq!(
+            solve(state.ruleset.layout, &state.cells, budget),
+            Solution::Unknown
+        );
+    }
+
+    #[test]
+    fn equivalent_positions_have_the_same_key() {
+        let layout = Layout::default();
+        let mut cells: Cells = Default::default();
+        cells[0] = vec![CARD_BACK];
+        cells[5] = vec![3];
+        cells[8] = vec![4, 12];
+        cells[9] = vec![25];
+
+        let mut swapped = cells.clone();
+        swapped.swap(0, 2);
+        swapped.swap(5, 7);
+        swapped.swap(8, 15);
+
+        assert_eq!(canonical(layout, &cells), canonical(layout, &swapped));
+
+        swapped[15].push(1);
+        assert_ne!(canonical(layout, &cells), canonical(layout, &swapped));
+    }
+
+    #[test]
+    fn there_are_no_hints_under_other_rules() {
+        let ruleset = Ruleset {
+            b: vec![],
+            ..Ruleset::default()
+        };
+        let mut state = GameState::with_ruleset([1; 16], None, Arc::new(ruleset));
+
+        state.request_hint();
+        assert_eq!(state.hint, None);
+    }
+
+    #[test]
+    fn following_the_hints_wins_the_game() {
+        let mut state = GameState::new([1; 16], None);
+        let layout = state.ruleset.layout;
+        wait(&mut state);
+
+        for _ in 0..200 {
+            if state.win_done {
+                return;
+            }
+
+            state.request_hint();
+            let hint = state.hint.expect("no hint");
+
+            match hint {
+                Move::Cards {
+                    grabpos,
+                    depth,
+                    droppos,
+                } => {
+                    state.selectpos = grabpos;
+                    state.selectdepth = depth;
+                    press(&mut state, Button::A);
+                    assert!(state.selectdrop, "{:?}", hint);
+
+                    state.selectpos = droppos;
+                    press(&mut state, Button::A);
+                }
+                Move::Dragons { suit } => {
+                    state.selectpos = layout.button_column();
+                    state.selectdepth = suit;
+                    press(&mut state, Button::A);
+                }
+            }
+
+            assert!(state.hint.is_none(), "{:?} was not made", hint);
+            wait(&mut state);
+        }
+
+        panic!("the game was not won");
+    }
+}
@@ -0,0 +1,64 @@
+use inner_common::*;
+use {getcardnum, getsuit, Layout};
+
+/// The suits in the order `getsuit` numbers them, by the first letter of their colour. The black
+/// suit gets a K, like in CMYK, so it isn't mistaken for a B.
+pub const SUIT_LETTERS: [char; 3] = ['R', 'G', 'K'];
+
+/// Two characters for a card: its suit letter and number, or a D for number for a dragon. The
+/// flower is `FL` and the back of a card is `##`.
+pub fn card_text(card: u8) -> String {
+    if card == CARD_BACK {
+        return "##".to_owned();
+    }
+    if card == FLOWER_CARD {
+        return "FL".to_owned();
+    }
+
+    let letter = SUIT_LETTERS
+        .get(getsuit(card) as usize)
+        .cloned()
+        .unwrap_or('?');
+
+    match getcardnum(card) {
+        0 => format!("{}D", letter),
+        num => format!("{}{}", letter, num),
+    }
+}
+
+/// What the cell at `pos` is, like "free cell 0" or "column 3".
+pub fn cell_name(layout: Layout, pos: u8) -> String {
+    if pos < layout.button_column() {
+        format!("free cell {}", pos)
+    } else if pos == layout.button_column() {
+        "buttons".to_owned()
+    } else if pos == layout.flower_foundation() {
+        "flower".to_owned()
+    } else if pos <= layout.end_of_foundations() {
+        format!("foundation {}", pos - layout.start_of_foundations())
+    } else {
+        format!("column {}", pos - layout.start_of_tableau())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DeckSpec;
+
+    #[test]
+    fn every_card_has_its_own_text() {
+        let deck = DeckSpec::default();
+        let mut texts: Vec<String> = deck.cards().iter().map(|&card| card_text(card)).collect();
+        texts.push(card_text(CARD_BACK));
+        texts.sort();
+        texts.dedup();
+
+        assert_eq!(&card_text(0), "RD");
+        assert_eq!(&card_text(FIRST_GREEN_CARD + 9), "G9");
+        assert_eq!(&card_text(FIRST_BLACK_CARD + 1), "K1");
+        // The dragons of each suit are all the same card.
+        assert_eq!(texts.len(), 3 * 10 + 1 + 1);
+        assert!(texts.iter().all(|text| text.chars().count() == 2));
+    }
+}
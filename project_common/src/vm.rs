@@ -1,11 +1,18 @@
 use *;
 
+use std::sync::Arc;
+
 pub type Logger = Option<fn(&str) -> ()>;
 
 #[derive(Clone)]
 pub struct VM {
     stack_pointer: usize,
     stack: [u8; VM::STACK_SIZE],
+    return_stack_len: usize,
+    return_stack: [usize; VM::RETURN_STACK_SIZE],
+    // The instructions the running program may still execute, shared with the button programs it
+    // runs through `HANDLE_BUTTON_PRESS`.
+    steps_left: usize,
     pub logger: Logger,
 }
 
@@ -54,6 +61,9 @@ impl VM {
         VM {
             stack_pointer: usize::max_value(),
             stack: [0; VM::STACK_SIZE],
+            return_stack_len: 0,
+            return_stack: [0; VM::RETURN_STACK_SIZE],
+            steps_left: 0,
             logger,
         }
     }
@@ -73,23 +83,34 @@ pub fn log_assert(logger: Logger, is_fine: bool, s: &str) {
 
 impl VM {
     pub const STACK_SIZE: usize = 512;
+    pub const RETURN_STACK_SIZE: usize = 16;
 
-    pub fn pop(&mut self) -> u8 {
-        log_assert(self.logger, !self.is_empty(), "stack underflow!");
+    // Returns `None` if the stack is empty.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
 
         let output = self.stack[self.stack_pointer];
 
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 
-        return output;
+        Some(output)
     }
 
-    pub fn push(&mut self, byte: u8) {
-        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+    // Returns false, and leaves the stack as it was, if the stack is full.
+    #[must_use]
+    pub fn push(&mut self, byte: u8) -> bool {
+        let new_pointer = self.stack_pointer.wrapping_add(1);
 
-        log_assert(self.logger, !self.is_empty(), "stack overflow!");
+        if new_pointer >= VM::STACK_SIZE {
+            return false;
+        }
 
+        self.stack_pointer = new_pointer;
         self.stack[self.stack_pointer] = byte;
+
+        true
     }
 
     pub fn is_empty(&self) -> bool {
@@ -101,6 +122,35 @@ impl VM {
         for i in 0..VM::STACK_SIZE {
             self.stack[i] = 0;
         }
+        self.return_stack_len = 0;
+    }
+
+    // Returns false, and leaves the return stack as it was, if the return stack is full.
+    #[must_use]
+    pub fn push_return(&mut self, address: usize) -> bool {
+        if self.return_stack_len >= VM::RETURN_STACK_SIZE {
+            return false;
+        }
+
+        self.return_stack[self.return_stack_len] = address;
+        self.return_stack_len += 1;
+
+        true
+    }
+
+    // Returns `None` if the return stack is empty.
+    pub fn pop_return(&mut self) -> Option<usize> {
+        if self.return_stack_len == 0 {
+            return None;
+        }
+
+        self.return_stack_len -= 1;
+
+        Some(self.return_stack[self.return_stack_len])
+    }
+
+    pub fn return_stack_len(&self) -> usize {
+        self.return_stack_len
     }
 }
 
@@ -154,6 +204,12 @@ pub mod instructions {
         pub const OR: u8 = 0b1110;
         pub const NOT: u8 = 0b1111;
 
+        pub const DUP: u8 = 0b1_0000;
+        pub const SWAP: u8 = DUP | 1;
+        pub const OVER: u8 = DUP | 0b10;
+        pub const ROT: u8 = DUP | 0b11;
+        pub const FORGET_N: u8 = DUP | 0b100;
+
         const EQ_FLAG: u8 = 0b001;
         const GT_FLAG: u8 = 0b010;
         const LT_FLAG: u8 = 0b100;
@@ -168,6 +224,22 @@ pub mod instructions {
         pub const LE_BRANCH: u8 = LT_BRANCH | EQ_FLAG;
         pub const JUMP: u8 = BRANCH | EQ_FLAG | GT_FLAG | LT_FLAG;
 
+        const BACKWARD: u8 = 0b1000;
+
+        pub const IF_BACK: u8 = IF | BACKWARD;
+        pub const EQ_BRANCH_BACK: u8 = EQ_BRANCH | BACKWARD;
+        pub const NE_BRANCH_BACK: u8 = NE_BRANCH | BACKWARD;
+        pub const GT_BRANCH_BACK: u8 = GT_BRANCH | BACKWARD;
+        pub const GE_BRANCH_BACK: u8 = GE_BRANCH | BACKWARD;
+        pub const LT_BRANCH_BACK: u8 = LT_BRANCH | BACKWARD;
+        pub const LE_BRANCH_BACK: u8 = LE_BRANCH | BACKWARD;
+        pub const JUMP_BACK: u8 = JUMP | BACKWARD;
+
+        pub const LOOP: u8 = 0b1000_1111;
+
+        pub const CALL: u8 = 0b1000_1100;
+        pub const RET: u8 = 0b1000_1101;
+
         const BOOLEAN: u8 = 0b1010_0000;
 
         pub const EQ: u8 = BOOLEAN | EQ_FLAG;
@@ -196,6 +268,8 @@ pub mod instructions {
 
         pub const CAN_GRAB: u8 = 0b1110_0000;
         pub const HANDLE_BUTTON_PRESS: u8 = 0b1110_0001;
+        pub const DISCARD_CARD_AT: u8 = 0b1110_0010;
+        pub const PUT_CARD_AT: u8 = 0b1110_0011;
 
         pub const ASSERT_EMPTY_STACK: u8 = 0b1110_1000;
         pub const HALT_UNLESS: u8 = 0b1110_1001;
@@ -205,6 +279,8 @@ pub mod instructions {
 
         pub const GET_GRAB_CARD_NUM_OR_255: u8 = 0b1111_0010;
         pub const GET_DROP_CARD_NUM_OR_255: u8 = 0b1111_0011;
+        pub const GET_CELL_LEN_AT: u8 = 0b1111_0100;
+        pub const GET_TOP_CARD_AT_OR_255: u8 = 0b1111_0101;
 
         pub const GET_GRAB_CARD_SUIT_OR_255: u8 = 0b1111_0110;
         pub const GET_DROP_CARD_SUIT_OR_255: u8 = 0b1111_0111;
@@ -221,6 +297,76 @@ pub mod instructions {
 }
 pub use self::instructions::*;
 
+pub mod asm;
+pub mod disasm;
+pub mod verify;
+
+/// Returns true for the instructions that read the following byte as an operand rather than
+/// executing it.
+pub fn takes_operand(instruction: u8) -> bool {
+    matches!(
+        instruction,
+        LITERAL
+            | FORGET_N
+            | IF
+            | EQ_BRANCH
+            | NE_BRANCH
+            | GT_BRANCH
+            | GE_BRANCH
+            | LT_BRANCH
+            | LE_BRANCH
+            | JUMP
+            | CALL
+    ) || is_backward_branch(instruction)
+}
+
+/// Returns true for the instructions whose operand says where to continue. That is a jump offset
+/// for everything but `CALL`, which takes the absolute address of the subroutine.
+pub fn is_branch(instruction: u8) -> bool {
+    instruction != LITERAL && instruction != FORGET_N && takes_operand(instruction)
+}
+
+/// Returns true for the branch instructions that subtract their offset instead of adding it.
+pub fn is_backward_branch(instruction: u8) -> bool {
+    matches!(
+        instruction,
+        IF_BACK
+            | EQ_BRANCH_BACK
+            | NE_BRANCH_BACK
+            | GT_BRANCH_BACK
+            | GE_BRANCH_BACK
+            | LT_BRANCH_BACK
+            | LE_BRANCH_BACK
+            | JUMP_BACK
+            | LOOP
+    )
+}
+
+/// Where execution continues if the branch at `address` with the given `offset` is taken, or
+/// `None` if that would be before the start of the program. Offsets are measured from the byte
+/// after the operand in both directions, so an offset of 0 always continues with the next
+/// instruction.
+pub fn branch_target(instruction: u8, address: usize, offset: u8) -> Option<usize> {
+    let next = address + 2;
+
+    if instruction == CALL {
+        Some(offset as usize)
+    } else if is_backward_branch(instruction) {
+        next.checked_sub(offset as usize)
+    } else {
+        Some(next + offset as usize)
+    }
+}
+
+/// The inverse of `instruction_name`.
+pub fn instruction_by_name(name: &str) -> Option<u8> {
+    if name == NAME_FN_DEFAULT {
+        return None;
+    }
+
+    (0..=255u8).find(|&byte| instruction_name(byte) == name)
+}
+
 #[derive(PartialEq, Eq, Hash)]
 pub struct PrettyInstruction(pub u8);
 
@@ -241,37 +387,215 @@ impl fmt::Debug for PrettyInstruction {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VmError {
+    StackUnderflow {
+        ip: usize,
+    },
+    StackOverflow {
+        ip: usize,
+    },
+    UnknownOpcode {
+        ip: usize,
+        byte: u8,
+    },
+    AssertionFailed {
+        ip: usize,
+    },
+    /// The last byte of the program is an instruction that needs an operand.
+    MissingOperand {
+        ip: usize,
+    },
+    /// A backward branch would continue before the start of the program.
+    JumpOutOfBounds {
+        ip: usize,
+    },
+    /// A `CALL` was made with `VM::RETURN_STACK_SIZE` calls already unreturned.
+    ReturnStackOverflow {
+        ip: usize,
+    },
+    /// A `RET` was reached outside of any subroutine.
+    ReturnStackUnderflow {
+        ip: usize,
+    },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::StackUnderflow { ip } => write!(f, "stack underflow at {}", ip),
+            VmError::StackOverflow { ip } => write!(f, "stack overflow at {}", ip),
+            VmError::UnknownOpcode { ip, byte } => write!(f, "unknown opcode {} at {}", byte, ip),
+            VmError::AssertionFailed { ip } => write!(f, "ASSERT_EMPTY_STACK failed at {}", ip),
+            VmError::MissingOperand { ip } => write!(f, "missing operand at {}", ip),
+            VmError::JumpOutOfBounds { ip } => {
+                write!(f, "jump before the start of the program at {}", ip)
+            }
+            VmError::ReturnStackOverflow { ip } => write!(f, "return stack overflow at {}", ip),
+            VmError::ReturnStackUnderflow { ip } => write!(f, "return stack underflow at {}", ip),
+        }
+    }
+}
+
+impl ::std::error::Error for VmError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Execution ran off the end of the program.
+    Finished { steps: usize },
+    /// Execution stopped at a `HALT` or one of the conditional halting instructions.
+    Halted { steps: usize },
+    /// Execution was stopped after running `VmConfig::budget` instructions.
+    BudgetExhausted { steps: usize },
+}
+
+impl Outcome {
+    // The number of instructions that were executed.
+    pub fn steps(&self) -> usize {
+        match *self {
+            Outcome::Finished { steps }
+            | Outcome::Halted { steps }
+            | Outcome::BudgetExhausted { steps } => steps,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VmConfig {
+    /// The maximum number of instructions a single call to `interpret_with_config` will run,
+    /// counting those of the button programs it runs.
+    pub budget: usize,
+}
+
+impl VmConfig {
+    pub const DEFAULT_BUDGET: usize = 1 << 16;
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            budget: VmConfig::DEFAULT_BUDGET,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    Halt,
+    /// A button program run by the instruction used up the rest of the budget.
+    BudgetExhausted,
+}
+
 impl GameState {
-    pub fn interpret(&mut self, bytecode: &[u8]) {
+    pub fn interpret(&mut self, bytecode: &[u8]) -> Result<Outcome, VmError> {
+        self.interpret_with_config(bytecode, &VmConfig::default())
+    }
+
+    // Calls still unreturned when the program stops are dropped, so the return stack is left as
+    // it was found.
+    pub fn interpret_with_config(
+        &mut self,
+        bytecode: &[u8],
+        config: &VmConfig,
+    ) -> Result<Outcome, VmError> {
+        let return_stack_len = self.vm.return_stack_len;
+        self.vm.steps_left = config.budget;
+
+        let result = self.interpret_until_stopped(bytecode);
+
+        self.vm.return_stack_len = return_stack_len;
+
+        result
+    }
+
+    // The steps include those of any button programs run along the way.
+    fn interpret_until_stopped(&mut self, bytecode: &[u8]) -> Result<Outcome, VmError> {
+        let budget = self.vm.steps_left;
+
         let len = bytecode.len();
         let mut i = 0;
         while i < len {
+            if self.vm.steps_left == 0 {
+                return Ok(Outcome::BudgetExhausted { steps: budget });
+            }
+
             let instruction = bytecode[i];
 
-            self.interpret_instruction(bytecode, &mut i, instruction);
+            self.vm.steps_left -= 1;
+            let step = self.interpret_instruction(bytecode, &mut i, instruction)?;
+
+            match step {
+                Step::Continue => {}
+                Step::Halt => {
+                    return Ok(Outcome::Halted {
+                        steps: budget - self.vm.steps_left,
+                    });
+                }
+                Step::BudgetExhausted => {
+                    return Ok(Outcome::BudgetExhausted { steps: budget });
+                }
+            }
 
-            i += 1;
+            i = i.wrapping_add(1);
         }
+
+        Ok(Outcome::Finished {
+            steps: budget - self.vm.steps_left,
+        })
+    }
+
+    // Like `interpret_with_config`, but out of whatever budget the program running
+    // `HANDLE_BUTTON_PRESS` has left.
+    fn interpret_button_program(&mut self, bytecode: &[u8]) -> Result<Outcome, VmError> {
+        let return_stack_len = self.vm.return_stack_len;
+
+        let result = self.interpret_until_stopped(bytecode);
+
+        self.vm.return_stack_len = return_stack_len;
+
+        result
     }
 
+    // For trusted programs, where an error means a bug rather than something the caller can
+    // recover from.
+    pub fn interpret_or_log(&mut self, bytecode: &[u8]) {
+        if let Err(error) = self.interpret(bytecode) {
+            log(self.vm.logger, &error.to_string());
+        }
+    }
+
+    // Execution stops at the first error, so the last pointer in the output is where the error,
+    // if any, happened.
     pub fn interpret_and_return_visited_instruction_pointers(
         &mut self,
         bytecode: &[u8],
     ) -> Vec<usize> {
         let mut output: Vec<usize> = Vec::with_capacity(bytecode.len());
 
+        let return_stack_len = self.vm.return_stack_len;
+        self.vm.steps_left = VmConfig::default().budget;
+
         let len = bytecode.len();
         let mut i = 0;
-        while i < len {
+        while i < len && self.vm.steps_left > 0 {
             let instruction = bytecode[i];
 
-            self.interpret_instruction(bytecode, &mut i, instruction);
+            self.vm.steps_left -= 1;
+            let result = self.interpret_instruction(bytecode, &mut i, instruction);
 
             output.push(i);
 
-            i += 1;
+            match result {
+                Ok(Step::Continue) => {}
+                Ok(Step::Halt) | Ok(Step::BudgetExhausted) | Err(_) => break,
+            }
+
+            i = i.wrapping_add(1);
         }
 
+        self.vm.return_stack_len = return_stack_len;
+
         output
     }
 
@@ -281,12 +605,52 @@ impl GameState {
         bytecode: &[u8],
         instruction_pointer: &mut usize,
         instruction: u8,
-    ) {
+    ) -> Result<Step, VmError> {
+        let ip = *instruction_pointer;
+
+        macro_rules! pop {
+            () => {
+                self.vm.pop().ok_or(VmError::StackUnderflow { ip })?
+            };
+        }
+
+        macro_rules! push {
+            ($byte:expr) => {{
+                let byte = $byte;
+                if !self.vm.push(byte) {
+                    return Err(VmError::StackOverflow { ip });
+                }
+            }};
+        }
+
+        macro_rules! operand {
+            () => {{
+                *instruction_pointer += 1;
+                if *instruction_pointer >= bytecode.len() {
+                    return Err(VmError::MissingOperand { ip });
+                }
+                bytecode[*instruction_pointer]
+            }};
+        }
+
         macro_rules! jump_if {
             ($boolean:expr) => {
-                *instruction_pointer += 1;
-                if *instruction_pointer < bytecode.len() && $boolean {
-                    *instruction_pointer += bytecode[*instruction_pointer] as usize;
+                let offset = operand!();
+                if $boolean {
+                    *instruction_pointer += offset as usize;
+                }
+            };
+        }
+
+        // The instruction pointer is incremented once more after each instruction, so this sets
+        // it to one before the target, which wraps around when the target is 0.
+        macro_rules! jump_back_if {
+            ($boolean:expr) => {
+                let offset = operand!();
+                if $boolean {
+                    let target = branch_target(instruction, ip, offset)
+                        .ok_or(VmError::JumpOutOfBounds { ip })?;
+                    *instruction_pointer = target.wrapping_sub(1);
                 }
             };
         }
@@ -302,21 +666,21 @@ impl GameState {
         }
 
         macro_rules! halt {
-            () => {
-                *instruction_pointer = bytecode.len() - 1
-            };
+            () => {{
+                *instruction_pointer = bytecode.len() - 1;
+                return Ok(Step::Halt);
+            }};
         }
 
-        if cfg!(debug_assertions) || true {
-            log(
-                self.vm.logger,
-                &format!(
-                    "{:20} {} : {}",
-                    *instruction_pointer,
-                    self.vm,
-                    PrettyInstruction(instruction),
-                ),
-            );
+        // Formatting every step is most of the cost of running a program, so skip it when there's
+        // nowhere for it to go.
+        if let Some(logger) = self.vm.logger {
+            logger(&format!(
+                "{:20} {} : {}",
+                *instruction_pointer,
+                self.vm,
+                PrettyInstruction(instruction),
+            ));
         }
 
         match instruction {
@@ -328,141 +692,231 @@ impl GameState {
                 self.selectdrop = false;
             }
             LITERAL => {
-                *instruction_pointer += 1;
-                if *instruction_pointer < bytecode.len() {
-                    self.vm.push(bytecode[*instruction_pointer]);
-                }
+                let value = operand!();
+                push!(value);
             }
             FORGET => {
-                self.vm.pop();
+                pop!();
+            }
+            DUP => {
+                let a = pop!();
+                push!(a);
+                push!(a);
+            }
+            SWAP => {
+                let b = pop!();
+                let a = pop!();
+                push!(b);
+                push!(a);
+            }
+            OVER => {
+                let b = pop!();
+                let a = pop!();
+                push!(a);
+                push!(b);
+                push!(a);
+            }
+            // ( a b c -- b c a )
+            ROT => {
+                let c = pop!();
+                let b = pop!();
+                let a = pop!();
+                push!(b);
+                push!(c);
+                push!(a);
+            }
+            FORGET_N => {
+                let count = operand!();
+                for _ in 0..count {
+                    pop!();
+                }
             }
             ADD => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(a.wrapping_add(b));
+                let b = pop!();
+                let a = pop!();
+                push!(a.wrapping_add(b));
             }
             SUB => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(a.wrapping_sub(b));
+                let b = pop!();
+                let a = pop!();
+                push!(a.wrapping_sub(b));
             }
             MUL => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(a.wrapping_mul(b));
+                let b = pop!();
+                let a = pop!();
+                push!(a.wrapping_mul(b));
             }
             DIV => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(if b != 0 { a.wrapping_div(b) } else { 255 });
+                let b = pop!();
+                let a = pop!();
+                push!(if b != 0 { a.wrapping_div(b) } else { 255 });
             }
             MAX => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(if a > b { a } else { b });
+                let b = pop!();
+                let a = pop!();
+                push!(if a > b { a } else { b });
             }
             MIN => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(if a < b { a } else { b });
+                let b = pop!();
+                let a = pop!();
+                push!(if a < b { a } else { b });
             }
             AND => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(a & b);
+                let b = pop!();
+                let a = pop!();
+                push!(a & b);
             }
             OR => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(a | b);
+                let b = pop!();
+                let a = pop!();
+                push!(a | b);
             }
             NOT => {
-                let a = self.vm.pop();
-                self.vm.push(if a == 0 { 255 } else { 0 });
+                let a = pop!();
+                push!(if a == 0 { 255 } else { 0 });
             }
             IF => {
-                let a = self.vm.pop();
+                let a = pop!();
 
                 jump_if!(a != 0);
             }
             EQ_BRANCH => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
+                let b = pop!();
+                let a = pop!();
                 jump_if!(a == b);
             }
             NE_BRANCH => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
+                let b = pop!();
+                let a = pop!();
                 jump_if!(a != b);
             }
             GT_BRANCH => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
+                let b = pop!();
+                let a = pop!();
                 jump_if!(a > b);
             }
             GE_BRANCH => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
+                let b = pop!();
+                let a = pop!();
                 jump_if!(a >= b);
             }
             LT_BRANCH => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
+                let b = pop!();
+                let a = pop!();
                 jump_if!(a < b);
             }
             LE_BRANCH => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
+                let b = pop!();
+                let a = pop!();
                 jump_if!(a <= b);
             }
             JUMP => {
                 jump_if!(true);
             }
+            IF_BACK => {
+                let a = pop!();
+
+                jump_back_if!(a != 0);
+            }
+            EQ_BRANCH_BACK => {
+                let b = pop!();
+                let a = pop!();
+                jump_back_if!(a == b);
+            }
+            NE_BRANCH_BACK => {
+                let b = pop!();
+                let a = pop!();
+                jump_back_if!(a != b);
+            }
+            GT_BRANCH_BACK => {
+                let b = pop!();
+                let a = pop!();
+                jump_back_if!(a > b);
+            }
+            GE_BRANCH_BACK => {
+                let b = pop!();
+                let a = pop!();
+                jump_back_if!(a >= b);
+            }
+            LT_BRANCH_BACK => {
+                let b = pop!();
+                let a = pop!();
+                jump_back_if!(a < b);
+            }
+            LE_BRANCH_BACK => {
+                let b = pop!();
+                let a = pop!();
+                jump_back_if!(a <= b);
+            }
+            JUMP_BACK => {
+                jump_back_if!(true);
+            }
+            CALL => {
+                let address = operand!();
+                if !self.vm.push_return(*instruction_pointer + 1) {
+                    return Err(VmError::ReturnStackOverflow { ip });
+                }
+                *instruction_pointer = (address as usize).wrapping_sub(1);
+            }
+            RET => {
+                let address = self
+                    .vm
+                    .pop_return()
+                    .ok_or(VmError::ReturnStackUnderflow { ip })?;
+                *instruction_pointer = address.wrapping_sub(1);
+            }
+            // The counter stays on the stack, so the loop body can read it, and has to be
+            // forgotten after the loop. A counter of 0 runs the body once, like a counter of 1.
+            LOOP => {
+                let counter = pop!().saturating_sub(1);
+                push!(counter);
+                jump_back_if!(counter != 0);
+            }
             EQ => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(stack_bool!(a == b));
+                let b = pop!();
+                let a = pop!();
+                push!(stack_bool!(a == b));
             }
             NE => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(stack_bool!(a != b));
+                let b = pop!();
+                let a = pop!();
+                push!(stack_bool!(a != b));
             }
             GT => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(stack_bool!(a > b));
+                let b = pop!();
+                let a = pop!();
+                push!(stack_bool!(a > b));
             }
             GE => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(stack_bool!(a >= b));
+                let b = pop!();
+                let a = pop!();
+                push!(stack_bool!(a >= b));
             }
             LT => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(stack_bool!(a < b));
+                let b = pop!();
+                let a = pop!();
+                push!(stack_bool!(a < b));
             }
             LE => {
-                let b = self.vm.pop();
-                let a = self.vm.pop();
-                self.vm.push(stack_bool!(a <= b));
+                let b = pop!();
+                let a = pop!();
+                push!(stack_bool!(a <= b));
             }
-            GET_SELECT_POS => self.vm.push(self.selectpos),
+            GET_SELECT_POS => push!(self.selectpos),
             SET_SELECT_POS => {
-                self.selectpos = self.vm.pop() & 15;
+                self.selectpos = self.ruleset.layout.wrap(pop!()) as u8;
             }
-            GET_SELECT_DEPTH => self.vm.push(self.selectdepth),
+            GET_SELECT_DEPTH => push!(self.selectdepth),
             SET_SELECT_DEPTH => {
-                self.selectdepth = self.vm.pop();
+                self.selectdepth = pop!();
             }
-            GET_GRAB_POS => self.vm.push(self.grabpos),
+            GET_GRAB_POS => push!(self.grabpos),
             SET_GRAB_POS => {
-                self.grabpos = self.vm.pop() & 15;
+                self.grabpos = self.ruleset.layout.wrap(pop!()) as u8;
             }
-            GET_GRAB_DEPTH => self.vm.push(self.grabdepth),
+            GET_GRAB_DEPTH => push!(self.grabdepth),
             SET_GRAB_DEPTH => {
-                self.grabdepth = self.vm.pop();
+                self.grabdepth = pop!();
             }
             FILL_MOVE_TIMER => {
                 self.movetimer = MOVE_TIMER_MAX;
@@ -476,25 +930,59 @@ impl GameState {
                     ),
                 );
 
-                let output = if cangrab(&self.cells, self.selectpos, self.selectdepth) {
+                let output = if cangrab(
+                    self.ruleset.layout,
+                    &self.cells,
+                    self.selectpos,
+                    self.selectdepth,
+                ) {
                     255
                 } else {
                     0
                 };
 
-                self.vm.push(output);
+                push!(output);
             }
             HANDLE_BUTTON_PRESS => {
-                if canmovedragons(self, self.selectdepth) {
-                    movedragons(self);
-                    self.interpret(&[DROP, FILL_MOVE_TIMER]);
+                let ruleset = Arc::clone(&self.ruleset);
+                let index = self.selectdepth;
+
+                if let Some(button) = ruleset.special_buttons.get(index as usize) {
+                    push!(index);
+                    if let Outcome::BudgetExhausted { .. } =
+                        self.interpret_button_program(&button.is_enabled)?
+                    {
+                        return Ok(Step::BudgetExhausted);
+                    }
+
+                    if pop!() != 0 {
+                        push!(index);
+                        if let Outcome::BudgetExhausted { .. } =
+                            self.interpret_button_program(&button.on_press)?
+                        {
+                            return Ok(Step::BudgetExhausted);
+                        }
+                    }
                 }
             }
+            DISCARD_CARD_AT => {
+                let pos = pop!();
+
+                self.cells[self.ruleset.layout.wrap(pos)].pop();
+            }
+            PUT_CARD_AT => {
+                let pos = pop!();
+                let card = pop!();
+
+                self.cells[self.ruleset.layout.wrap(pos)].push(card);
+            }
             ASSERT_EMPTY_STACK => {
-                assert!(self.vm.is_empty(), "ASSERT_EMPTY_STACK failed!");
+                if !self.vm.is_empty() {
+                    return Err(VmError::AssertionFailed { ip });
+                }
             }
             HALT_UNLESS => {
-                let a = self.vm.pop();
+                let a = pop!();
                 if a == 0 {
                     halt!();
                 }
@@ -505,7 +993,7 @@ impl GameState {
                 if card == 255 {
                     halt!()
                 } else {
-                    self.vm.push(card);
+                    push!(card);
                 }
             }
             GET_DROP_CARD_OR_HALT => {
@@ -514,7 +1002,7 @@ impl GameState {
                 if card == 255 {
                     halt!()
                 } else {
-                    self.vm.push(card);
+                    push!(card);
                 }
             }
             GET_GRAB_CARD_NUM_OR_255 => {
@@ -522,68 +1010,84 @@ impl GameState {
 
                 let output = if card == 255 { 255 } else { getcardnum(card) };
 
-                self.vm.push(output);
+                push!(output);
             }
             GET_DROP_CARD_NUM_OR_255 => {
                 let card = self.get_drop_card_or_255();
 
                 let output = if card == 255 { 255 } else { getcardnum(card) };
 
-                self.vm.push(output);
+                push!(output);
             }
             GET_GRAB_CARD_SUIT_OR_255 => {
                 let card = self.get_grab_card_or_255();
 
                 let output = if card == 255 { 255 } else { getsuit(card) };
 
-                self.vm.push(output);
+                push!(output);
             }
             GET_DROP_CARD_SUIT_OR_255 => {
                 let card = self.get_drop_card_or_255();
 
                 let output = if card == 255 { 255 } else { getsuit(card) };
 
-                self.vm.push(output);
+                push!(output);
             }
             GET_CARD_NUM => {
-                let card = self.vm.pop();
-                self.vm.push(getcardnum(card));
+                let card = pop!();
+                push!(getcardnum(card));
             }
             GET_CARD_SUIT => {
-                let card = self.vm.pop();
-                self.vm.push(getsuit(card));
+                let card = pop!();
+                push!(getsuit(card));
             }
             GET_GRAB_CARD_OR_255 => {
                 let output = self.get_grab_card_or_255();
 
-                self.vm.push(output);
+                push!(output);
             }
             GET_DROP_CARD_OR_255 => {
                 let output = self.get_drop_card_or_255();
 
-                self.vm.push(output);
+                push!(output);
             }
             MOVE_CARDS => {
-                let droppos = self.vm.pop();
-                let grabdepth = self.vm.pop();
-                let grabpos = self.vm.pop();
+                let droppos = pop!();
+                let grabdepth = pop!();
+                let grabpos = pop!();
 
-                movecards(self, grabpos, grabdepth, droppos);
+                movecards(self.ruleset.layout, &mut self.cells, grabpos, grabdepth, droppos);
             }
             GET_SELECT_DROP => {
-                self.vm.push(stack_bool!(self.selectdrop));
+                push!(stack_bool!(self.selectdrop));
             }
             GET_CELL_LEN => {
-                self.vm
-                    .push(self.cells[self.selectpos as usize & 15].len() as u8);
+                push!(self.cells[self.ruleset.layout.wrap(self.selectpos)].len() as u8);
+            }
+            GET_CELL_LEN_AT => {
+                let pos = pop!();
+
+                push!(self.cells[self.ruleset.layout.wrap(pos)].len() as u8);
+            }
+            GET_TOP_CARD_AT_OR_255 => {
+                let pos = pop!();
+
+                push!(self.cells[self.ruleset.layout.wrap(pos)].last().cloned().unwrap_or(255));
             }
             HALT => halt!(),
-            _ => unimplemented!(),
+            _ => {
+                return Err(VmError::UnknownOpcode {
+                    ip,
+                    byte: instruction,
+                })
+            }
         }
+
+        Ok(Step::Continue)
     }
 
     fn get_grab_card_or_255(&self) -> u8 {
-        let grabpos = self.grabpos as usize & 15;
+        let grabpos = self.ruleset.layout.wrap(self.grabpos);
         let grabdepth = self.grabdepth as usize;
         let cells = &self.cells;
 
@@ -596,7 +1100,7 @@ impl GameState {
     }
 
     fn get_drop_card_or_255(&self) -> u8 {
-        let droppos = self.selectpos as usize & 15;
+        let droppos = self.ruleset.layout.wrap(self.selectpos);
         let cells = &self.cells;
 
         let len = cells[droppos].len();
@@ -607,3 +1111,352 @@ impl GameState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn popping_an_empty_stack_is_an_error() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[GET_SELECT_POS, ADD]),
+            Err(VmError::StackUnderflow { ip: 1 })
+        );
+    }
+
+    #[test]
+    fn pushing_onto_a_full_stack_is_an_error() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        let bytecode = vec![GET_SELECT_POS; VM::STACK_SIZE + 1];
+
+        assert_eq!(
+            game_state.interpret(&bytecode),
+            Err(VmError::StackOverflow { ip: VM::STACK_SIZE })
+        );
+    }
+
+    #[test]
+    fn unknown_opcodes_are_an_error() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[NO_OP, 200]),
+            Err(VmError::UnknownOpcode { ip: 1, byte: 200 })
+        );
+    }
+
+    #[test]
+    fn failed_assertions_are_an_error() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[GET_SELECT_POS, ASSERT_EMPTY_STACK]),
+            Err(VmError::AssertionFailed { ip: 1 })
+        );
+    }
+
+    #[test]
+    fn missing_operands_are_an_error() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[NO_OP, LITERAL]),
+            Err(VmError::MissingOperand { ip: 1 })
+        );
+        assert_eq!(
+            game_state.interpret(&[JUMP]),
+            Err(VmError::MissingOperand { ip: 0 })
+        );
+    }
+
+    #[test]
+    fn halting_is_distinguished_from_finishing() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[NO_OP]),
+            Ok(Outcome::Finished { steps: 1 })
+        );
+        assert_eq!(
+            game_state.interpret(&[HALT, NO_OP]),
+            Ok(Outcome::Halted { steps: 1 })
+        );
+        assert_eq!(
+            game_state.interpret(&[LITERAL, 0, HALT_UNLESS, NO_OP]),
+            Ok(Outcome::Halted { steps: 2 })
+        );
+        assert_eq!(
+            game_state.interpret(&[JUMP, 1, HALT]),
+            Ok(Outcome::Finished { steps: 1 })
+        );
+    }
+
+    #[test]
+    fn execution_stops_when_the_budget_runs_out() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        let config = VmConfig { budget: 3 };
+
+        assert_eq!(
+            game_state.interpret_with_config(&[NO_OP; 3], &config),
+            Ok(Outcome::Finished { steps: 3 })
+        );
+        assert_eq!(
+            game_state.interpret_with_config(&[GET_SELECT_POS; 4], &config),
+            Ok(Outcome::BudgetExhausted { steps: 3 })
+        );
+        assert_eq!(game_state.vm.to_string(), "[8, 8, 8]");
+    }
+
+    #[test]
+    fn backward_branches_loop_until_the_condition_fails() {
+        let mut game_state = GameState::new([0; 16], None);
+        game_state.selectpos = 0;
+
+        let bytecode = asm::assemble(
+            "
+            increment:
+                GET_SELECT_POS
+                LITERAL 1
+                ADD
+                SET_SELECT_POS
+                GET_SELECT_POS
+                LITERAL 5
+                NE_BRANCH_BACK increment
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            game_state.interpret(&bytecode),
+            Ok(Outcome::Finished { steps: 35 })
+        );
+        assert_eq!(game_state.selectpos, 5);
+    }
+
+    #[test]
+    fn loop_runs_its_body_once_per_count() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        let bytecode = asm::assemble("LITERAL 4\nbody: NO_OP\nLOOP body").unwrap();
+        assert_eq!(bytecode, vec![LITERAL, 4, NO_OP, LOOP, 3]);
+
+        assert_eq!(
+            game_state.interpret(&bytecode),
+            Ok(Outcome::Finished { steps: 9 })
+        );
+        assert_eq!(game_state.vm.to_string(), "[0]");
+    }
+
+    #[test]
+    fn infinite_loops_run_out_of_budget() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret_with_config(&[JUMP_BACK, 2], &VmConfig { budget: 100 }),
+            Ok(Outcome::BudgetExhausted { steps: 100 })
+        );
+    }
+
+    #[test]
+    fn button_programs_share_the_budget() {
+        use ruleset::{Ruleset, SpecialButton};
+        use std::sync::Arc;
+
+        let ruleset = Ruleset {
+            special_buttons: vec![SpecialButton {
+                is_enabled: vec![FORGET, LITERAL, 1],
+                on_press: vec![FORGET, NO_OP],
+            }],
+            ..Ruleset::default()
+        };
+        let mut game_state = GameState::with_ruleset([0; 16], None, Arc::new(ruleset));
+        game_state.selectdepth = 0;
+
+        assert_eq!(
+            game_state.interpret(&[HANDLE_BUTTON_PRESS]),
+            Ok(Outcome::Finished { steps: 5 })
+        );
+        assert_eq!(
+            game_state.interpret_with_config(&[HANDLE_BUTTON_PRESS, NO_OP], &VmConfig { budget: 4 }),
+            Ok(Outcome::BudgetExhausted { steps: 4 })
+        );
+    }
+
+    #[test]
+    fn jumping_before_the_start_is_an_error() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[NO_OP, JUMP_BACK, 4]),
+            Err(VmError::JumpOutOfBounds { ip: 1 })
+        );
+        assert_eq!(
+            game_state.interpret(&[NO_OP, JUMP_BACK, 3]),
+            Ok(Outcome::BudgetExhausted {
+                steps: VmConfig::DEFAULT_BUDGET
+            })
+        );
+    }
+
+    #[test]
+    fn subroutines_return_to_after_the_call() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        let bytecode = asm::assemble(
+            "
+                LITERAL 1
+                CALL double
+                CALL double
+                HALT
+            double:
+                LITERAL 2
+                MUL
+                RET
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            game_state.interpret(&bytecode),
+            Ok(Outcome::Halted { steps: 10 })
+        );
+        assert_eq!(game_state.vm.to_string(), "[4]");
+        assert_eq!(game_state.vm.return_stack_len(), 0);
+    }
+
+    #[test]
+    fn returning_outside_a_subroutine_is_an_error() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[NO_OP, RET]),
+            Err(VmError::ReturnStackUnderflow { ip: 1 })
+        );
+    }
+
+    #[test]
+    fn unbounded_recursion_overflows_the_return_stack() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[NO_OP, CALL, 0]),
+            Err(VmError::ReturnStackOverflow { ip: 1 })
+        );
+        assert_eq!(game_state.vm.return_stack_len(), 0);
+    }
+
+    #[test]
+    fn halting_inside_a_subroutine_leaves_the_return_stack_as_it_was() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        assert_eq!(
+            game_state.interpret(&[CALL, 3, NO_OP, HALT]),
+            Ok(Outcome::Halted { steps: 2 })
+        );
+        assert_eq!(game_state.vm.return_stack_len(), 0);
+    }
+
+    fn stack_after(source: &str) -> String {
+        let mut game_state = GameState::new([0; 16], None);
+
+        game_state
+            .interpret(&asm::assemble(source).unwrap())
+            .unwrap();
+
+        game_state.vm.to_string()
+    }
+
+    #[test]
+    fn dup_copies_the_top() {
+        assert_eq!(stack_after("LITERAL 1\nLITERAL 2\nDUP"), "[1, 2, 2]");
+    }
+
+    #[test]
+    fn swap_exchanges_the_top_two() {
+        assert_eq!(stack_after("LITERAL 1\nLITERAL 2\nSWAP"), "[2, 1]");
+    }
+
+    #[test]
+    fn over_copies_the_second() {
+        assert_eq!(stack_after("LITERAL 1\nLITERAL 2\nOVER"), "[1, 2, 1]");
+    }
+
+    #[test]
+    fn rot_brings_the_third_to_the_top() {
+        assert_eq!(
+            stack_after("LITERAL 1\nLITERAL 2\nLITERAL 3\nROT"),
+            "[2, 3, 1]"
+        );
+    }
+
+    #[test]
+    fn forget_n_forgets_that_many() {
+        assert_eq!(
+            stack_after("LITERAL 1\nLITERAL 2\nLITERAL 3\nFORGET_N 2"),
+            "[1]"
+        );
+        assert_eq!(stack_after("LITERAL 1\nFORGET_N 0"), "[1]");
+
+        let mut game_state = GameState::new([0; 16], None);
+        assert_eq!(
+            game_state.interpret(&[LITERAL, 1, FORGET_N, 2]),
+            Err(VmError::StackUnderflow { ip: 2 })
+        );
+    }
+
+    #[test]
+    fn get_cell_len_at_reads_any_cell() {
+        assert_eq!(
+            stack_after("LITERAL START_OF_TABLEAU\nGET_CELL_LEN_AT\nLITERAL 0\nGET_CELL_LEN_AT"),
+            "[5, 0]"
+        );
+    }
+
+    #[test]
+    fn cards_can_be_discarded_and_put_anywhere() {
+        let mut game_state = GameState::new([0; 16], None);
+        let top = game_state.cells[START_OF_TABLEAU as usize].clone();
+
+        game_state
+            .interpret(&[
+                LITERAL,
+                START_OF_TABLEAU,
+                DISCARD_CARD_AT,
+                LITERAL,
+                CARD_BACK,
+                LITERAL,
+                1,
+                PUT_CARD_AT,
+            ])
+            .unwrap();
+
+        assert_eq!(
+            game_state.cells[START_OF_TABLEAU as usize],
+            &top[..top.len() - 1]
+        );
+        assert_eq!(game_state.cells[1], vec![CARD_BACK]);
+        assert!(game_state.vm.is_empty());
+    }
+
+    #[test]
+    fn shufflers_need_enough_on_the_stack() {
+        let mut game_state = GameState::new([0; 16], None);
+
+        for &(instruction, needed) in [(DUP, 1), (SWAP, 2), (OVER, 2), (ROT, 3)].iter() {
+            let mut bytecode = vec![GET_SELECT_POS; needed - 1];
+            bytecode.push(instruction);
+
+            assert_eq!(
+                game_state.interpret(&bytecode),
+                Err(VmError::StackUnderflow { ip: needed - 1 }),
+                "{}",
+                instruction_name(instruction)
+            );
+            game_state.vm.clear();
+        }
+    }
+}
@@ -0,0 +1,614 @@
+// A textual assembly format for the bytecode that `GameState::interpret` runs, so branch offsets
+// don't need to be counted by hand. For example:
+//
+//     ; comments start with `;` or `//`
+//         GET_SELECT_POS
+//         LITERAL BUTTON_COLUMN
+//         NE_BRANCH not_button
+//         HANDLE_BUTTON_PRESS
+//         HALT
+//     not_button:
+//         LITERAL START_OF_TABLEAU - 1
+//
+// Mnemonics are the names of the constants in `vm::instructions`. `LITERAL` and `FORGET_N` take a
+// number or an expression of numbers and the symbolic constants in `SYMBOLS` added or subtracted
+// together.
+// The branch instructions take a label or a raw numeric offset. The label of a plain branch must
+// be defined further down the program, and the label of a `_BACK` branch or `LOOP` must be
+// defined further up, (or on the branch itself.) `CALL` takes a label anywhere in the first 256
+// bytes, or a raw address, so a subroutine is just a label followed by code that ends in `RET`:
+//
+//         CALL clamp
+//         HALT
+//     clamp:
+//         LITERAL 3
+//         MIN
+//         RET
+//
+// `.byte` emits its operand as is, which is how the disassembler writes out bytes that are not
+// valid instructions.
+
+use inner_common::*;
+use vm::instructions::CALL;
+use vm::{instruction_by_name, is_backward_branch, is_branch, takes_operand};
+
+use std::collections::HashMap;
+use std::fmt;
+
+pub const SYMBOLS: [(&str, u8); 15] = [
+    ("MOVE_TIMER_MAX", MOVE_TIMER_MAX),
+    ("MAX_SUIT_NUM", MAX_SUIT_NUM),
+    ("BUTTON_COLUMN", BUTTON_COLUMN),
+    ("SPECIAL_BUTTON_COUNT", SPECIAL_BUTTON_COUNT),
+    ("FLOWER_FOUNDATION", FLOWER_FOUNDATION),
+    ("START_OF_FOUNDATIONS", START_OF_FOUNDATIONS),
+    ("END_OF_FOUNDATIONS", END_OF_FOUNDATIONS),
+    ("START_OF_TABLEAU", START_OF_TABLEAU),
+    ("CELLS_MAX_INDEX", CELLS_MAX_INDEX),
+    ("FIRST_GREEN_CARD", FIRST_GREEN_CARD),
+    ("FIRST_BLACK_CARD", FIRST_BLACK_CARD),
+    ("FLOWER_CARD", FLOWER_CARD),
+    ("CARD_BACK", CARD_BACK),
+    ("TRUE", 255),
+    ("FALSE", 0),
+];
+
+pub const BYTE_DIRECTIVE: &str = ".byte";
+
+pub fn symbol_value(name: &str) -> Option<u8> {
+    SYMBOLS
+        .iter()
+        .find(|&&(symbol, _)| symbol == name)
+        .map(|&(_, value)| value)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    UnknownSymbol(String),
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    InvalidLabel(String),
+    /// The branch target is in the other direction than the branch goes, or further than 255
+    /// bytes away. For `CALL`, `offset` is the address, which is past 255.
+    JumpOutOfRange {
+        label: String,
+        offset: isize,
+    },
+    ValueOutOfRange(i64),
+    MissingOperand,
+    UnexpectedOperand,
+    InvalidOperand(String),
+}
+
+/// `line` and `column` are both 1-based.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+
+        match self.kind {
+            AsmErrorKind::UnknownMnemonic(ref name) => write!(f, "unknown mnemonic `{}`", name),
+            AsmErrorKind::UnknownSymbol(ref name) => write!(f, "unknown symbol `{}`", name),
+            AsmErrorKind::UndefinedLabel(ref name) => write!(f, "undefined label `{}`", name),
+            AsmErrorKind::DuplicateLabel(ref name) => {
+                write!(f, "label `{}` is defined more than once", name)
+            }
+            AsmErrorKind::InvalidLabel(ref name) => write!(f, "`{}` is not a valid label", name),
+            AsmErrorKind::JumpOutOfRange { ref label, offset } => write!(
+                f,
+                "jump to `{}` needs an offset of {}, but only 0 to 255 is possible",
+                label, offset
+            ),
+            AsmErrorKind::ValueOutOfRange(value) => {
+                write!(f, "{} does not fit in a byte", value)
+            }
+            AsmErrorKind::MissingOperand => write!(f, "missing operand"),
+            AsmErrorKind::UnexpectedOperand => write!(f, "this instruction takes no operand"),
+            AsmErrorKind::InvalidOperand(ref operand) => {
+                write!(f, "`{}` is not a valid operand", operand)
+            }
+        }
+    }
+}
+
+impl ::std::error::Error for AsmError {}
+
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    fn error(self, kind: AsmErrorKind) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+}
+
+enum Item<'a> {
+    Byte(u8),
+    Value(u8),
+    Target {
+        label: &'a str,
+        position: Position,
+        instruction: u8,
+    },
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with_symbols(source, &[])
+}
+
+/// Like `assemble`, but `symbols` are looked up before `SYMBOLS`, so a program can be assembled
+/// against a board that is not laid out like the default one.
+pub fn assemble_with_symbols(source: &str, symbols: &[(&str, u8)]) -> Result<Vec<u8>, AsmError> {
+    let mut items: Vec<Item> = Vec::new();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+
+    for (line_index, full_line) in source.lines().enumerate() {
+        let line = strip_comment(full_line);
+
+        let mut tokens = tokenize(line, line_index + 1);
+
+        if let Some(&(first, position)) = tokens.first() {
+            if let Some(label) = first.strip_suffix(':') {
+                if !is_identifier(label) {
+                    return Err(position.error(AsmErrorKind::InvalidLabel(label.to_string())));
+                }
+
+                if labels.insert(label, items.len()).is_some() {
+                    return Err(position.error(AsmErrorKind::DuplicateLabel(label.to_string())));
+                }
+
+                tokens.remove(0);
+            }
+        }
+
+        if tokens.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, position) = tokens[0];
+        let operand = &tokens[1..];
+
+        if mnemonic == BYTE_DIRECTIVE {
+            if operand.is_empty() {
+                return Err(Position {
+                    line: position.line,
+                    column: position.column + mnemonic.len(),
+                }
+                .error(AsmErrorKind::MissingOperand));
+            }
+
+            items.push(Item::Value(evaluate(operand, symbols)?));
+            continue;
+        }
+
+        let instruction = instruction_by_name(mnemonic)
+            .ok_or_else(|| position.error(AsmErrorKind::UnknownMnemonic(mnemonic.to_string())))?;
+
+        items.push(Item::Byte(instruction));
+
+        if takes_operand(instruction) {
+            if operand.is_empty() {
+                return Err(Position {
+                    line: position.line,
+                    column: position.column + mnemonic.len(),
+                }
+                .error(AsmErrorKind::MissingOperand));
+            }
+
+            if is_branch(instruction) && operand.len() == 1 && is_identifier(operand[0].0) {
+                let (label, position) = operand[0];
+                items.push(Item::Target {
+                    label,
+                    position,
+                    instruction,
+                });
+            } else {
+                items.push(Item::Value(evaluate(operand, symbols)?));
+            }
+        } else if let Some(&(_, position)) = operand.first() {
+            return Err(position.error(AsmErrorKind::UnexpectedOperand));
+        }
+    }
+
+    let mut output = Vec::with_capacity(items.len());
+
+    for (address, item) in items.iter().enumerate() {
+        let byte = match *item {
+            Item::Byte(byte) | Item::Value(byte) => byte,
+            Item::Target {
+                label,
+                position,
+                instruction,
+            } => {
+                let target = *labels.get(label).ok_or_else(|| {
+                    position.error(AsmErrorKind::UndefinedLabel(label.to_string()))
+                })?;
+
+                let offset = if instruction == CALL {
+                    target as isize
+                } else if is_backward_branch(instruction) {
+                    (address as isize + 1) - target as isize
+                } else {
+                    target as isize - (address as isize + 1)
+                };
+
+                if !(0..=255).contains(&offset) {
+                    return Err(position.error(AsmErrorKind::JumpOutOfRange {
+                        label: label.to_string(),
+                        offset,
+                    }));
+                }
+
+                offset as u8
+            }
+        };
+
+        output.push(byte);
+    }
+
+    Ok(output)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let end = match (line.find(';'), line.find("//")) {
+        (Some(a), Some(b)) => a.min(b),
+        (Some(a), None) | (None, Some(a)) => a,
+        (None, None) => line.len(),
+    };
+
+    &line[..end]
+}
+
+// Splits on whitespace, but also makes `+` and `-` their own tokens so `A-1` and `A - 1` are
+// treated the same.
+fn tokenize(line: &str, line_number: usize) -> Vec<(&str, Position)> {
+    let mut output = Vec::new();
+    let mut start = None;
+
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() || c == '+' || c == '-' {
+            if let Some(s) = start.take() {
+                output.push((&line[s..i], position_of(line, s, line_number)));
+            }
+            if !c.is_whitespace() {
+                output.push((&line[i..i + 1], position_of(line, i, line_number)));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+
+    if let Some(s) = start {
+        output.push((&line[s..], position_of(line, s, line_number)));
+    }
+
+    output
+}
+
+fn position_of(line: &str, byte_index: usize, line_number: usize) -> Position {
+    Position {
+        line: line_number,
+        column: line[..byte_index].chars().count() + 1,
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn evaluate(tokens: &[(&str, Position)], symbols: &[(&str, u8)]) -> Result<u8, AsmError> {
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    let mut expecting_term = true;
+
+    for &(token, position) in tokens {
+        if expecting_term {
+            let value = if let Some(value) = parse_number(token) {
+                value
+            } else if is_identifier(token) {
+                symbols
+                    .iter()
+                    .find(|&&(symbol, _)| symbol == token)
+                    .map(|&(_, value)| value)
+                    .or_else(|| symbol_value(token))
+                    .ok_or_else(|| position.error(AsmErrorKind::UnknownSymbol(token.to_string())))?
+                    as i64
+            } else {
+                return Err(position.error(AsmErrorKind::InvalidOperand(token.to_string())));
+            };
+
+            total += sign * value;
+            expecting_term = false;
+        } else {
+            sign = match token {
+                "+" => 1,
+                "-" => -1,
+                _ => return Err(position.error(AsmErrorKind::InvalidOperand(token.to_string()))),
+            };
+            expecting_term = true;
+        }
+    }
+
+    let (_, last_position) = tokens[tokens.len() - 1];
+
+    if expecting_term {
+        return Err(last_position.error(AsmErrorKind::MissingOperand));
+    }
+
+    if !(0..=255).contains(&total) {
+        let (_, first_position) = tokens[0];
+        return Err(first_position.error(AsmErrorKind::ValueOutOfRange(total)));
+    }
+
+    Ok(total as u8)
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    if let Some(hex) = token.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = token.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else if token.chars().all(|c| c.is_ascii_digit()) {
+        token.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::instructions::*;
+
+    #[test]
+    fn assembles_the_left_button_program() {
+        let source = "
+                GET_SELECT_POS
+                LITERAL 0
+                EQ_BRANCH wrap_to_top_row_end
+                GET_SELECT_POS
+                LITERAL START_OF_TABLEAU
+                EQ_BRANCH wrap_to_tableau_end
+                GET_SELECT_POS
+                LITERAL 1
+                SUB
+                JUMP set_pos
+            wrap_to_tableau_end:
+                LITERAL CELLS_MAX_INDEX
+                JUMP set_pos
+            wrap_to_top_row_end:
+                LITERAL START_OF_TABLEAU - 1
+            set_pos:
+                SET_SELECT_POS
+                ASSERT_EMPTY_STACK
+                GET_SELECT_DROP
+                IF dropping
+                GET_CELL_LEN
+                LITERAL 1
+                SUB
+                GET_SELECT_DEPTH
+                LITERAL 0
+                MAX
+                MIN
+                JUMP set_depth
+            dropping:
+                LITERAL 0
+            set_depth:
+                SET_SELECT_DEPTH
+        ";
+
+        let expected = vec![
+            GET_SELECT_POS,
+            LITERAL,
+            0,
+            EQ_BRANCH,
+            15,
+            GET_SELECT_POS,
+            LITERAL,
+            START_OF_TABLEAU,
+            EQ_BRANCH,
+            6,
+            GET_SELECT_POS,
+            LITERAL,
+            1,
+            SUB,
+            JUMP,
+            6,
+            LITERAL,
+            CELLS_MAX_INDEX,
+            JUMP,
+            2,
+            LITERAL,
+            START_OF_TABLEAU - 1,
+            SET_SELECT_POS,
+            ASSERT_EMPTY_STACK,
+            GET_SELECT_DROP,
+            IF,
+            11,
+            GET_CELL_LEN,
+            LITERAL,
+            1,
+            SUB,
+            GET_SELECT_DEPTH,
+            LITERAL,
+            0,
+            MAX,
+            MIN,
+            JUMP,
+            2,
+            LITERAL,
+            0,
+            SET_SELECT_DEPTH,
+        ];
+
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test]
+    fn labels_can_share_a_line_and_numbers_can_be_offsets() {
+        let source = "JUMP 1 // skip the halt
+                      HALT
+                      end: LITERAL 0x10 ; hex
+                      LITERAL 0b11";
+
+        assert_eq!(
+            assemble(source),
+            Ok(vec![JUMP, 1, HALT, LITERAL, 16, LITERAL, 3])
+        );
+    }
+
+    #[test]
+    fn a_label_at_the_very_end_is_allowed() {
+        assert_eq!(assemble("IF end\nHALT\nend:"), Ok(vec![IF, 1, HALT]));
+    }
+
+    #[test]
+    fn the_byte_directive_emits_raw_bytes() {
+        assert_eq!(
+            assemble(".byte 200\n.byte LITERAL_IS_NOT_A_SYMBOL"),
+            Err(AsmError {
+                line: 2,
+                column: 7,
+                kind: AsmErrorKind::UnknownSymbol("LITERAL_IS_NOT_A_SYMBOL".to_string()),
+            })
+        );
+        assert_eq!(assemble(".byte 200\n.byte 0x2A"), Ok(vec![200, LITERAL]));
+    }
+
+    #[test]
+    fn unknown_mnemonics_report_their_position() {
+        assert_eq!(
+            assemble("NO_OP\n   BOGUS"),
+            Err(AsmError {
+                line: 2,
+                column: 4,
+                kind: AsmErrorKind::UnknownMnemonic("BOGUS".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn undefined_labels_report_their_position() {
+        assert_eq!(
+            assemble("JUMP  nowhere"),
+            Err(AsmError {
+                line: 1,
+                column: 7,
+                kind: AsmErrorKind::UndefinedLabel("nowhere".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn backward_jumps_are_out_of_range() {
+        assert_eq!(
+            assemble("start:\nNO_OP\nJUMP start"),
+            Err(AsmError {
+                line: 3,
+                column: 6,
+                kind: AsmErrorKind::JumpOutOfRange {
+                    label: "start".to_string(),
+                    offset: -3,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn backward_branches_take_labels_from_further_up() {
+        assert_eq!(
+            assemble("start:\nNO_OP\nJUMP_BACK start\nLITERAL 3\nself: LOOP self"),
+            Ok(vec![NO_OP, JUMP_BACK, 3, LITERAL, 3, LOOP, 2])
+        );
+        assert_eq!(
+            assemble("IF_BACK end\nNO_OP\nend:").map_err(|e| e.kind),
+            Err(AsmErrorKind::JumpOutOfRange {
+                label: "end".to_string(),
+                offset: -1,
+            })
+        );
+    }
+
+    #[test]
+    fn calls_take_absolute_addresses() {
+        assert_eq!(
+            assemble("CALL sub\nHALT\nsub: RET\nCALL sub"),
+            Ok(vec![CALL, 3, HALT, RET, CALL, 3])
+        );
+    }
+
+    #[test]
+    fn jumps_further_than_a_byte_are_out_of_range() {
+        let mut source = "JUMP far\n".to_string();
+        for _ in 0..256 {
+            source.push_str("NO_OP\n");
+        }
+        source.push_str("far:");
+
+        assert_eq!(
+            assemble(&source).map_err(|e| e.kind),
+            Err(AsmErrorKind::JumpOutOfRange {
+                label: "far".to_string(),
+                offset: 256,
+            })
+        );
+    }
+
+    #[test]
+    fn operands_are_checked() {
+        assert_eq!(
+            assemble("LITERAL").map_err(|e| e.kind),
+            Err(AsmErrorKind::MissingOperand)
+        );
+        assert_eq!(
+            assemble("ADD 1").map_err(|e| e.kind),
+            Err(AsmErrorKind::UnexpectedOperand)
+        );
+        assert_eq!(
+            assemble("LITERAL 0 - 1").map_err(|e| e.kind),
+            Err(AsmErrorKind::ValueOutOfRange(-1))
+        );
+        assert_eq!(
+            assemble("LITERAL NOT_A_SYMBOL").map_err(|e| e.kind),
+            Err(AsmErrorKind::UnknownSymbol("NOT_A_SYMBOL".to_string()))
+        );
+        assert_eq!(
+            assemble("x:\nx:").map_err(|e| e.kind),
+            Err(AsmErrorKind::DuplicateLabel("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn given_symbols_shadow_the_built_in_ones() {
+        let symbols = [("START_OF_TABLEAU", 6), ("COLUMNS", 4)];
+
+        assert_eq!(
+            assemble_with_symbols(
+                "LITERAL START_OF_TABLEAU + COLUMNS\nLITERAL BUTTON_COLUMN",
+                &symbols
+            ),
+            Ok(vec![LITERAL, 10, LITERAL, BUTTON_COLUMN])
+        );
+    }
+}
@@ -0,0 +1,328 @@
+// Turns bytecode back into the text format `asm::assemble` accepts, with synthesized labels for
+// branch targets and a blank line after each instruction that never falls through, so the basic
+// blocks are visible. `assemble(&disassemble(bytecode).to_string())` gives back `bytecode`.
+
+use vm::asm::BYTE_DIRECTIVE;
+use vm::instructions::*;
+use vm::{branch_target, is_branch, takes_operand, NAME_FN_DEFAULT};
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Plain(u8),
+    /// `LITERAL` or `FORGET_N`, along with its operand.
+    Value {
+        instruction: u8,
+        value: u8,
+    },
+    /// `target` is the address execution continues at if the branch is taken, or `None` if a
+    /// backward branch would continue before the start of the program.
+    Branch {
+        instruction: u8,
+        offset: u8,
+        target: Option<usize>,
+    },
+    /// A byte that cannot be decoded as an instruction, either because it is not a known opcode
+    /// or because it needs an operand and is the last byte of the program.
+    Byte(u8),
+}
+
+impl Operation {
+    pub fn size(&self) -> usize {
+        match *self {
+            Operation::Plain(_) | Operation::Byte(_) => 1,
+            Operation::Value { .. } | Operation::Branch { .. } => 2,
+        }
+    }
+
+    // Whether execution can continue on to the next operation.
+    pub fn falls_through(&self) -> bool {
+        match *self {
+            Operation::Plain(HALT) | Operation::Plain(RET) => false,
+            Operation::Branch { instruction, .. } => {
+                instruction != JUMP && instruction != JUMP_BACK
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    pub operation: Operation,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    /// The synthesized label names, keyed by the address they point at. Only branch targets that
+    /// land on the start of an instruction, (or one past the end of the program,) get a label.
+    pub labels: HashMap<usize, String>,
+    pub len: usize,
+}
+
+pub fn disassemble(bytecode: &[u8]) -> Disassembly {
+    let len = bytecode.len();
+    let mut lines = Vec::with_capacity(len);
+
+    let mut address = 0;
+    while address < len {
+        let byte = bytecode[address];
+
+        let operation = if instruction_name(byte) == NAME_FN_DEFAULT {
+            Operation::Byte(byte)
+        } else if takes_operand(byte) {
+            if address + 1 >= len {
+                Operation::Byte(byte)
+            } else if is_branch(byte) {
+                let offset = bytecode[address + 1];
+                Operation::Branch {
+                    instruction: byte,
+                    offset,
+                    target: branch_target(byte, address, offset),
+                }
+            } else {
+                Operation::Value {
+                    instruction: byte,
+                    value: bytecode[address + 1],
+                }
+            }
+        } else {
+            Operation::Plain(byte)
+        };
+
+        lines.push(Line { address, operation });
+
+        address += operation.size();
+    }
+
+    let mut targets: Vec<usize> = lines
+        .iter()
+        .filter_map(|line| match line.operation {
+            Operation::Branch { target, .. } => target,
+            _ => None,
+        })
+        .filter(|&target| target == len || lines.iter().any(|line| line.address == target))
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    let labels = targets
+        .into_iter()
+        .enumerate()
+        .map(|(i, target)| {
+            let name = if target == len {
+                "end".to_string()
+            } else {
+                format!("L{}", i)
+            };
+            (target, name)
+        })
+        .collect();
+
+    Disassembly { lines, labels, len }
+}
+
+impl Disassembly {
+    // The addresses that start a basic block: the first instruction, every labeled instruction
+    // and every instruction that follows a branch, a `HALT` or a `RET`.
+    pub fn block_starts(&self) -> Vec<usize> {
+        let mut output = Vec::new();
+
+        let mut previous: Option<Operation> = None;
+        for line in self.lines.iter() {
+            let starts_block = match previous {
+                None => true,
+                Some(Operation::Branch { .. })
+                | Some(Operation::Plain(HALT))
+                | Some(Operation::Plain(RET)) => true,
+                _ => self.labels.contains_key(&line.address),
+            };
+
+            if starts_block {
+                output.push(line.address);
+            }
+
+            previous = Some(line.operation);
+        }
+
+        output
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.lines.iter() {
+            if let Some(label) = self.labels.get(&line.address) {
+                writeln!(f, "{}:", label)?;
+            }
+
+            match line.operation {
+                Operation::Plain(instruction) => {
+                    writeln!(f, "    {}", instruction_name(instruction))?;
+                }
+                Operation::Value { instruction, value } => {
+                    writeln!(f, "    {} {}", instruction_name(instruction), value)?;
+                }
+                Operation::Branch {
+                    instruction,
+                    offset,
+                    target,
+                } => {
+                    if let Some(label) = target.and_then(|target| self.labels.get(&target)) {
+                        writeln!(f, "    {} {}", instruction_name(instruction), label)?;
+                    } else {
+                        writeln!(f, "    {} {}", instruction_name(instruction), offset)?;
+                    }
+                }
+                Operation::Byte(byte) => {
+                    writeln!(f, "    {} {}", BYTE_DIRECTIVE, byte)?;
+                }
+            }
+
+            if !line.operation.falls_through() {
+                writeln!(f)?;
+            }
+        }
+
+        if let Some(label) = self.labels.get(&self.len) {
+            writeln!(f, "{}:", label)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::asm::assemble;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    #[test]
+    fn operands_are_printed_as_numbers_and_targets_as_labels() {
+        let bytecode = [
+            GET_SELECT_POS,
+            LITERAL,
+            GET_SELECT_POS,
+            EQ_BRANCH,
+            1,
+            HALT,
+            JUMP,
+            0,
+        ];
+
+        assert_eq!(
+            disassemble(&bytecode).to_string(),
+            "    GET_SELECT_POS
+    LITERAL 64
+    EQ_BRANCH L0
+    HALT
+
+L0:
+    JUMP end
+
+end:
+"
+        );
+    }
+
+    #[test]
+    fn targets_inside_an_operand_keep_their_offset() {
+        let bytecode = [JUMP, 1, LITERAL, NO_OP];
+
+        let disassembly = disassemble(&bytecode);
+
+        assert!(disassembly.labels.is_empty());
+        assert_eq!(disassembly.to_string(), "    JUMP 1\n\n    LITERAL 0\n");
+    }
+
+    #[test]
+    fn unknown_and_truncated_instructions_become_bytes() {
+        assert_eq!(
+            disassemble(&[200, LITERAL]).to_string(),
+            "    .byte 200\n    .byte 42\n"
+        );
+    }
+
+    #[test]
+    fn block_starts() {
+        let bytecode = assemble(
+            "
+                GET_SELECT_DROP
+                IF dropping
+                GET_CELL_LEN
+                HALT
+            dropping:
+                LITERAL 0
+                SET_SELECT_DEPTH
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(disassemble(&bytecode).block_starts(), vec![0, 3, 5]);
+    }
+
+    #[test]
+    fn subroutines_are_labeled_by_address() {
+        let bytecode = [CALL, 3, HALT, GET_SELECT_POS, RET];
+
+        assert_eq!(
+            disassemble(&bytecode).to_string(),
+            "    CALL L0
+    HALT
+
+L0:
+    GET_SELECT_POS
+    RET
+
+"
+        );
+        assert_eq!(disassemble(&bytecode).block_starts(), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn backward_targets_get_labels_too() {
+        let bytecode = [LITERAL, 3, NO_OP, LOOP, 3, JUMP_BACK, 2, JUMP_BACK, 10];
+
+        assert_eq!(
+            disassemble(&bytecode).to_string(),
+            "    LITERAL 3
+L0:
+    NO_OP
+    LOOP L0
+L1:
+    JUMP_BACK L1
+
+    JUMP_BACK 10
+
+"
+        );
+    }
+
+    #[test]
+    fn random_bytecode_round_trips_through_the_assembler() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+
+        for _ in 0..1000 {
+            let len = rng.gen_range(0, 64);
+            let bytecode: Vec<u8> = (0..len)
+                .map(|_| {
+                    if rng.gen_bool(0.25) {
+                        rng.gen_range(0, 8)
+                    } else {
+                        rng.gen()
+                    }
+                })
+                .collect();
+
+            let text = disassemble(&bytecode).to_string();
+
+            assert_eq!(assemble(&text), Ok(bytecode), "{}", text);
+        }
+    }
+}
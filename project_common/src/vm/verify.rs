@@ -0,0 +1,593 @@
+// A static check of the stack depth of a program. Every branch is assumed to be able to go either
+// way, so a program passes only if every path through it keeps the stack between empty and
+// `VM::STACK_SIZE`, and every path that reaches an instruction does so with the same depth.
+// Subroutines are checked once per entry address and stack depth on entry, so a subroutine may be
+// called with different depths, and the depths it returns with carry back to every matching call.
+
+use vm::instructions::*;
+use vm::{branch_target, is_branch, takes_operand, VM};
+
+use std::collections::{HashMap, HashSet};
+
+// A subroutine's entry address and the stack depth it was called with, or `None` for the code
+// that runs outside of any subroutine.
+type Frame = Option<(usize, usize)>;
+
+// The number of bytes popped and then pushed by an instruction, or `None` if the byte is not an
+// instruction. `FORGET_N` also pops as many bytes as its operand says.
+pub fn stack_effect(instruction: u8) -> Option<(usize, usize)> {
+    let effect = match instruction {
+        NO_OP | FILL_MOVE_TIMER | GRAB | DROP | JUMP | JUMP_BACK | CALL | RET
+        | HANDLE_BUTTON_PRESS | ASSERT_EMPTY_STACK | HALT | FORGET_N => (0, 0),
+        GET_SELECT_POS
+        | GET_SELECT_DEPTH
+        | GET_GRAB_POS
+        | GET_GRAB_DEPTH
+        | LITERAL
+        | CAN_GRAB
+        | GET_GRAB_CARD_OR_HALT
+        | GET_DROP_CARD_OR_HALT
+        | GET_GRAB_CARD_NUM_OR_255
+        | GET_DROP_CARD_NUM_OR_255
+        | GET_GRAB_CARD_SUIT_OR_255
+        | GET_DROP_CARD_SUIT_OR_255
+        | GET_GRAB_CARD_OR_255
+        | GET_DROP_CARD_OR_255
+        | GET_SELECT_DROP
+        | GET_CELL_LEN => (0, 1),
+        NOT | GET_CARD_NUM | GET_CARD_SUIT | GET_CELL_LEN_AT | GET_TOP_CARD_AT_OR_255 | LOOP => {
+            (1, 1)
+        }
+        DUP => (1, 2),
+        SWAP => (2, 2),
+        OVER => (2, 3),
+        ROT => (3, 3),
+        IF | IF_BACK | FORGET | SET_SELECT_POS | SET_SELECT_DEPTH | SET_GRAB_POS
+        | SET_GRAB_DEPTH | HALT_UNLESS | DISCARD_CARD_AT => (1, 0),
+        ADD | SUB | MUL | DIV | MAX | MIN | AND | OR | EQ | NE | GT | GE | LT | LE => (2, 1),
+        EQ_BRANCH | NE_BRANCH | GT_BRANCH | GE_BRANCH | LT_BRANCH | LE_BRANCH | EQ_BRANCH_BACK
+        | NE_BRANCH_BACK | GT_BRANCH_BACK | GE_BRANCH_BACK | LT_BRANCH_BACK | LE_BRANCH_BACK => {
+            (2, 0)
+        }
+        PUT_CARD_AT => (2, 0),
+        MOVE_CARDS => (3, 0),
+        _ => return None,
+    };
+
+    Some(effect)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    UnknownOpcode(u8),
+    MissingOperand,
+    StackUnderflow {
+        depth: usize,
+        needed: usize,
+    },
+    StackOverflow {
+        depth: usize,
+    },
+    /// The branch would continue at `target`, which is more than one past the last instruction.
+    JumpPastEnd {
+        target: usize,
+    },
+    /// The backward branch would continue before the first instruction.
+    JumpBeforeStart,
+    /// The `CALL` can be reached with `VM::RETURN_STACK_SIZE` calls already unreturned.
+    ReturnStackOverflow,
+    /// The `RET` can be reached outside of any subroutine.
+    ReturnWithoutCall,
+    /// Two paths reach the same instruction with different stack depths.
+    InconsistentDepth {
+        expected: usize,
+        found: usize,
+    },
+    /// `ASSERT_EMPTY_STACK` can be reached with a non-empty stack.
+    AssertionFails {
+        depth: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub index: usize,
+    pub kind: ViolationKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// The stack depth just before each instruction executes, or `None` for bytes that are
+    /// never executed. That includes operands. For a subroutine called with several depths, this
+    /// is whichever one was found first.
+    pub depths: Vec<Option<usize>>,
+    pub violations: Vec<Violation>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+pub fn verify(bytecode: &[u8]) -> Report {
+    verify_with_arguments(bytecode, 0)
+}
+
+/// Like `verify`, for programs that start with `arguments` values already on the stack.
+pub fn verify_with_arguments(bytecode: &[u8], arguments: usize) -> Report {
+    let len = bytecode.len();
+
+    let mut depths: Vec<Option<usize>> = vec![None; len];
+    let mut violations: Vec<Violation> = Vec::new();
+
+    let mut context_depths: HashMap<(usize, Frame), usize> = HashMap::new();
+    // The return addresses and frames of the calls to each subroutine.
+    let mut callers: HashMap<(usize, usize), Vec<(usize, Frame)>> = HashMap::new();
+    // The depths each subroutine has been found to return with.
+    let mut returned_depths: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    // Every call, as the calling frame, the index of the `CALL` and the called frame.
+    let mut calls: Vec<(Frame, usize, (usize, usize))> = Vec::new();
+
+    let mut pending: Vec<(usize, Frame)> = Vec::new();
+    if len > 0 {
+        context_depths.insert((0, None), arguments);
+        pending.push((0, None));
+    }
+
+    macro_rules! violation {
+        ($index:expr, $kind:expr) => {{
+            let violation = Violation {
+                index: $index,
+                kind: $kind,
+            };
+            if !violations.contains(&violation) {
+                violations.push(violation);
+            }
+        }};
+    }
+
+    while let Some((index, frame)) = pending.pop() {
+        let instruction = bytecode[index];
+        let depth = context_depths[&(index, frame)];
+
+        if depths[index].is_none() {
+            depths[index] = Some(depth);
+        }
+
+        let (mut pops, pushes) = match stack_effect(instruction) {
+            Some(effect) => effect,
+            None => {
+                violation!(index, ViolationKind::UnknownOpcode(instruction));
+                continue;
+            }
+        };
+
+        if takes_operand(instruction) && index + 1 >= len {
+            violation!(index, ViolationKind::MissingOperand);
+            continue;
+        }
+
+        if instruction == FORGET_N {
+            pops += bytecode[index + 1] as usize;
+        }
+
+        if depth < pops {
+            violation!(
+                index,
+                ViolationKind::StackUnderflow {
+                    depth,
+                    needed: pops,
+                }
+            );
+            continue;
+        }
+
+        let new_depth = depth - pops + pushes;
+        if new_depth > VM::STACK_SIZE {
+            violation!(index, ViolationKind::StackOverflow { depth: new_depth });
+            continue;
+        }
+
+        if instruction == ASSERT_EMPTY_STACK && depth != 0 {
+            violation!(index, ViolationKind::AssertionFails { depth });
+            continue;
+        }
+
+        let mut successors = Vec::with_capacity(2);
+
+        if instruction == CALL {
+            let target = bytecode[index + 1] as usize;
+
+            if target > len {
+                violation!(index, ViolationKind::JumpPastEnd { target });
+            } else {
+                let called = (target, new_depth);
+                successors.push((target, Some(called), new_depth));
+
+                callers.entry(called).or_default().push((index + 2, frame));
+                calls.push((frame, index, called));
+
+                if let Some(returned) = returned_depths.get(&called) {
+                    for &returned_depth in returned {
+                        successors.push((index + 2, frame, returned_depth));
+                    }
+                }
+            }
+        } else if instruction == RET {
+            match frame {
+                Some(called) => {
+                    let returned = returned_depths.entry(called).or_default();
+
+                    if !returned.contains(&new_depth) {
+                        returned.push(new_depth);
+
+                        for &(address, caller) in &callers[&called] {
+                            successors.push((address, caller, new_depth));
+                        }
+                    }
+                }
+                None => violation!(index, ViolationKind::ReturnWithoutCall),
+            }
+        } else if is_branch(instruction) {
+            match branch_target(instruction, index, bytecode[index + 1]) {
+                Some(target) if target > len => {
+                    violation!(index, ViolationKind::JumpPastEnd { target });
+                }
+                Some(target) => successors.push((target, frame, new_depth)),
+                None => violation!(index, ViolationKind::JumpBeforeStart),
+            }
+
+            if instruction != JUMP && instruction != JUMP_BACK {
+                successors.push((index + 2, frame, new_depth));
+            }
+        } else if instruction != HALT {
+            let next = index + if takes_operand(instruction) { 2 } else { 1 };
+            successors.push((next, frame, new_depth));
+        }
+
+        for (successor, successor_frame, successor_depth) in successors {
+            if successor >= len {
+                continue;
+            }
+
+            let key = (successor, successor_frame);
+
+            match context_depths.get(&key).cloned() {
+                None => {
+                    context_depths.insert(key, successor_depth);
+                    pending.push(key);
+                }
+                Some(expected) if expected != successor_depth => {
+                    violation!(
+                        successor,
+                        ViolationKind::InconsistentDepth {
+                            expected,
+                            found: successor_depth,
+                        }
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    // Walk the calls from the code outside of any subroutine, counting the unreturned calls. A
+    // frame is only revisited with a count it has not had yet, so recursion stops at the limit.
+    let mut reached: HashSet<(Frame, usize)> = HashSet::new();
+    let mut unvisited: Vec<(Frame, usize)> = vec![(None, 0)];
+    while let Some((frame, unreturned)) = unvisited.pop() {
+        for &(_, index, called) in calls.iter().filter(|call| call.0 == frame) {
+            if unreturned >= VM::RETURN_STACK_SIZE {
+                violation!(index, ViolationKind::ReturnStackOverflow);
+            } else if reached.insert((Some(called), unreturned + 1)) {
+                unvisited.push((Some(called), unreturned + 1));
+            }
+        }
+    }
+
+    violations.sort_by_key(|violation| violation.index);
+
+    Report { depths, violations }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::asm::assemble;
+
+    fn violations(source: &str) -> Vec<Violation> {
+        verify(&assemble(source).unwrap()).violations
+    }
+
+    #[test]
+    fn every_instruction_has_a_stack_effect() {
+        for byte in 0..=255u8 {
+            assert_eq!(
+                stack_effect(byte).is_some(),
+                instruction_name(byte) != ::vm::NAME_FN_DEFAULT,
+                "{}",
+                byte
+            );
+        }
+    }
+
+    #[test]
+    fn depths_are_tracked_down_both_sides_of_a_branch() {
+        let report = verify(
+            &assemble(
+                "
+                    GET_SELECT_DROP
+                    IF dropping
+                    GET_CELL_LEN
+                    JUMP set_depth
+                dropping:
+                    LITERAL 0
+                set_depth:
+                    SET_SELECT_DEPTH
+                ",
+            )
+            .unwrap(),
+        );
+
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(
+            report.depths,
+            vec![
+                Some(0),
+                Some(1),
+                None,
+                Some(0),
+                Some(1),
+                None,
+                Some(0),
+                None,
+                Some(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn underflow_is_reported() {
+        assert_eq!(
+            violations("LITERAL 1\nADD"),
+            vec![Violation {
+                index: 2,
+                kind: ViolationKind::StackUnderflow {
+                    depth: 1,
+                    needed: 2,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn arguments_start_on_the_stack() {
+        let bytecode = assemble("LITERAL 1\nADD").unwrap();
+
+        let report = verify_with_arguments(&bytecode, 1);
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert_eq!(report.depths, vec![Some(1), None, Some(2)]);
+    }
+
+    #[test]
+    fn joins_with_different_depths_are_reported() {
+        assert_eq!(
+            violations("GET_SELECT_DROP\nIF skip\nGET_CELL_LEN\nskip:\nFORGET"),
+            vec![
+                Violation {
+                    index: 4,
+                    kind: ViolationKind::InconsistentDepth {
+                        expected: 0,
+                        found: 1,
+                    },
+                },
+                Violation {
+                    index: 4,
+                    kind: ViolationKind::StackUnderflow {
+                        depth: 0,
+                        needed: 1,
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn forget_n_pops_its_operand() {
+        assert!(verify(&assemble("GET_SELECT_POS\nDUP\nFORGET_N 2").unwrap()).is_ok());
+        assert_eq!(
+            violations("GET_SELECT_POS\nDUP\nFORGET_N 3"),
+            vec![Violation {
+                index: 2,
+                kind: ViolationKind::StackUnderflow {
+                    depth: 2,
+                    needed: 3,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn overflow_is_reported() {
+        let mut source = String::new();
+        for _ in 0..=VM::STACK_SIZE {
+            source.push_str("GET_SELECT_POS\n");
+        }
+
+        assert_eq!(
+            violations(&source),
+            vec![Violation {
+                index: VM::STACK_SIZE,
+                kind: ViolationKind::StackOverflow {
+                    depth: VM::STACK_SIZE + 1,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn jumping_past_the_end_is_reported() {
+        assert_eq!(
+            verify(&[JUMP, 1]).violations,
+            vec![Violation {
+                index: 0,
+                kind: ViolationKind::JumpPastEnd { target: 3 },
+            }]
+        );
+        assert!(verify(&[JUMP, 0]).is_ok());
+    }
+
+    #[test]
+    fn loops_are_checked_like_any_other_join() {
+        let report = verify(&assemble("LITERAL 3\nbody: NO_OP\nLOOP body\nFORGET").unwrap());
+
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(
+            report.depths,
+            vec![Some(0), None, Some(1), Some(1), None, Some(1)]
+        );
+
+        assert_eq!(
+            violations("again:\nGET_SELECT_POS\nIF_BACK again\nGET_SELECT_POS\nJUMP_BACK again"),
+            vec![Violation {
+                index: 0,
+                kind: ViolationKind::InconsistentDepth {
+                    expected: 0,
+                    found: 1,
+                },
+            }]
+        );
+        assert_eq!(
+            verify(&[JUMP_BACK, 3]).violations,
+            vec![Violation {
+                index: 0,
+                kind: ViolationKind::JumpBeforeStart,
+            }]
+        );
+    }
+
+    #[test]
+    fn subroutines_are_checked_for_each_call() {
+        let report = verify(
+            &assemble(
+                "
+                    CALL push_pos
+                    CALL push_pos
+                    ADD
+                    SET_SELECT_POS
+                    HALT
+                push_pos:
+                    GET_SELECT_POS
+                    RET
+                ",
+            )
+            .unwrap(),
+        );
+
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.depths[7], Some(0));
+
+        assert_eq!(
+            violations("CALL sub\nFORGET\nsub: RET"),
+            vec![Violation {
+                index: 2,
+                kind: ViolationKind::StackUnderflow {
+                    depth: 0,
+                    needed: 1,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn nested_calls_are_checked_once_per_subroutine() {
+        // Every subroutine calls the next one twice, so there are 2^14 paths to the last one.
+        let mut source = String::from("CALL sub_0\nHALT\n");
+        for sub in 0..14 {
+            source.push_str(&format!(
+                "sub_{0}:\nCALL sub_{1}\nCALL sub_{1}\nRET\n",
+                sub,
+                sub + 1
+            ));
+        }
+        source.push_str("sub_14:\nGET_SELECT_POS\nFORGET\nRET\n");
+
+        let report = verify(&assemble(&source).unwrap());
+        assert!(report.is_ok(), "{:?}", report.violations);
+    }
+
+    #[test]
+    fn recursion_overflows_the_return_stack() {
+        assert_eq!(
+            violations(
+                "
+                    CALL sub
+                    HALT
+                sub:
+                    GET_SELECT_DROP
+                    IF done
+                    CALL sub
+                done:
+                    RET
+                "
+            ),
+            vec![Violation {
+                index: 6,
+                kind: ViolationKind::ReturnStackOverflow,
+            }]
+        );
+    }
+
+    #[test]
+    fn bad_calls_and_returns_are_reported() {
+        assert_eq!(
+            verify(&[RET]).violations,
+            vec![Violation {
+                index: 0,
+                kind: ViolationKind::ReturnWithoutCall,
+            }]
+        );
+        assert_eq!(
+            verify(&[NO_OP, CALL, 0]).violations,
+            vec![Violation {
+                index: 1,
+                kind: ViolationKind::ReturnStackOverflow,
+            }]
+        );
+    }
+
+    #[test]
+    fn bad_bytes_are_reported() {
+        assert_eq!(
+            verify(&[NO_OP, 200]).violations,
+            vec![Violation {
+                index: 1,
+                kind: ViolationKind::UnknownOpcode(200),
+            }]
+        );
+        assert_eq!(
+            verify(&[LITERAL]).violations,
+            vec![Violation {
+                index: 0,
+                kind: ViolationKind::MissingOperand,
+            }]
+        );
+    }
+
+    #[test]
+    fn unreachable_code_is_not_checked() {
+        assert!(verify(&[HALT, ADD]).is_ok());
+    }
+
+    #[test]
+    fn failing_assertions_are_reported() {
+        assert_eq!(
+            violations("GET_SELECT_POS\nASSERT_EMPTY_STACK"),
+            vec![Violation {
+                index: 1,
+                kind: ViolationKind::AssertionFails { depth: 1 },
+            }]
+        );
+    }
+}
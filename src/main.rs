@@ -17,9 +17,6 @@ use stdweb::web::{self, Element, IElement, IEventTarget, INode, INonElementParen
 
 use stdweb::{UnsafeTypedArray, Value};
 
-mod game;
-use game::update_and_render;
-
 macro_rules! enclose {
     ( [$( $x:ident ),*] $y:expr ) => {
         {
@@ -292,27 +289,6 @@ impl PinkyWeb {
 
     fn on_key(&mut self, key: &str, location: KeyboardLocation, is_pressed: bool) -> bool {
         let button = match (key, location) {
-            ("Enter", _) => Button::Start,
-            ("Shift", KeyboardLocation::Right) => Button::Select,
-            ("ArrowUp", _) => Button::Up,
-            ("ArrowLeft", _) => Button::Left,
-            ("ArrowRight", _) => Button::Right,
-            ("ArrowDown", _) => Button::Down,
-
-            // On Edge the arrows have different names
-            // for some reason.
-            ("Up", _) => Button::Up,
-            ("Left", _) => Button::Left,
-            ("Right", _) => Button::Right,
-            ("Down", _) => Button::Down,
-
-            ("z", _) => Button::A,
-            ("x", _) => Button::B,
-
-            // For those using the Dvorak layout.
-            (";", _) => Button::A,
-            ("q", _) => Button::B,
-
             // For those using the Dvorak layout **and** Microsoft Edge.
             //
             // On `keydown` we get ";" as we should, but on `keyup`
@@ -320,7 +296,24 @@ impl PinkyWeb {
             // your browser be?
             ("Unidentified", _) if is_pressed == false => Button::A,
 
-            _ => return false,
+            ("r", _) => {
+                if is_pressed {
+                    self.state.log_replay();
+                }
+                return true;
+            }
+
+            ("h", _) => {
+                if is_pressed {
+                    self.state.game_state.request_hint();
+                }
+                return true;
+            }
+
+            _ => match button_for_key(key, location == KeyboardLocation::Right) {
+                Some(button) => button,
+                None => return false,
+            },
         };
 
         PinkyWeb::set_button_state(self, button, is_pressed);
@@ -340,6 +333,7 @@ pub struct State {
     pub game_state: GameState,
     pub framebuffer: Framebuffer,
     pub input: Input,
+    pub replay: Replay,
 }
 
 fn logger(s: &str) {
@@ -353,24 +347,46 @@ impl State {
     pub fn new() -> State {
         let framebuffer = Framebuffer::new();
 
-        let seed = unsafe {
-            let time = Date::new().get_time();
+        // A hash like `#deal=12` asks for that deal from the catalogue.
+        let catalogue_deal = catalogue_number()
+            .and_then(|number| dealing::catalogue_seed(number).map(|seed| (number, seed)));
+
+        let seed = match catalogue_deal {
+            Some((_, seed)) => seed,
+            None => unsafe {
+                let time = Date::new().get_time();
 
-            mem::transmute::<[f64; 2], [u8; 16]>([time, 1.0 / time])
+                mem::transmute::<[f64; 2], [u8; 16]>([time, 1.0 / time])
+            },
         };
 
         logger(&format!("{:?}", seed));
 
+        let mut game_state = GameState::new(seed, Some(logger));
+        if let Some((number, _)) = catalogue_deal {
+            game_state.dealing = Dealing::Catalogue(number);
+        }
+        let replay = Replay::with_dealing(seed, game_state.dealing);
+
         State {
-            game_state: GameState::new(seed, Some(logger)),
+            game_state,
             framebuffer,
             input: Input::new(),
+            replay,
         }
     }
 }
 
+fn catalogue_number() -> Option<usize> {
+    let hash = web::window().location()?.hash().ok()?;
+
+    hash.strip_prefix("#deal=")?.parse().ok()
+}
+
 impl State {
     pub fn frame(&mut self) {
+        self.replay.record(self.input.gamepad);
+
         update_and_render(&mut self.framebuffer, &mut self.game_state, self.input);
 
         self.input.previous_gamepad = self.input.gamepad;
@@ -383,6 +399,18 @@ impl State {
     pub fn release(&mut self, button: Button::Ty) {
         self.input.gamepad.remove(button);
     }
+
+    // Logs the replay so far as hex, to be pasted into a bug report.
+    pub fn log_replay(&self) {
+        let hex: String = self
+            .replay
+            .to_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        logger(&format!("replay: {}", hex));
+    }
 }
 
 fn emulate_for_a_single_frame(pinky: Rc<RefCell<PinkyWeb>>) {
@@ -0,0 +1,517 @@
+// Plays the game in a terminal. Cards are drawn as the two characters `card_text` gives them, and
+// the keys are the ones the browser version uses, as far as a terminal can tell them apart.
+
+extern crate libc;
+extern crate project_common;
+use project_common::*;
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::ops::Range;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const FRAME: Duration = Duration::from_millis(16);
+
+const USAGE: &str = "usage: tui [--deal N]
+
+    --deal N   play this game from the deal catalogue, instead of a random one";
+
+const HELP: &str =
+    "z or ;: A   x or q: B   arrows: move   Enter: Start   Backspace: Select   h: hint   \
+    Ctrl-C: quit";
+
+// Puts the terminal in raw mode, so keys come in as soon as they are pressed and aren't echoed,
+// and puts it back the way it was when dropped.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        unsafe {
+            let mut original: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            // Reads return straight away, with whatever has been typed so far.
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(RawMode { original })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Button(Button::Ty),
+    Hint,
+    Quit,
+}
+
+// Turns what was typed into keys. Arrow keys come in as escape sequences, and anything that isn't
+// a button is left out.
+fn keys(mut bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+
+    while !bytes.is_empty() {
+        let (button, len) = match *bytes {
+            [3, ..] | [4, ..] => {
+                keys.push(Key::Quit);
+                bytes = &bytes[1..];
+                continue;
+            }
+            [b'h', ..] => {
+                keys.push(Key::Hint);
+                bytes = &bytes[1..];
+                continue;
+            }
+            [0x1b, b'[', arrow, ..] | [0x1b, b'O', arrow, ..] => {
+                let name = match arrow {
+                    b'A' => "ArrowUp",
+                    b'B' => "ArrowDown",
+                    b'C' => "ArrowRight",
+                    b'D' => "ArrowLeft",
+                    _ => "",
+                };
+
+                (button_for_key(name, false), 3)
+            }
+            [b'\r', ..] | [b'\n', ..] => (button_for_key("Enter", false), 1),
+            // Terminals don't say when Shift is pressed on its own, so Backspace stands in for the
+            // right Shift key.
+            [0x7f, ..] | [0x08, ..] => (Some(Button::Select), 1),
+            [byte, ..] => (button_for_key(&(byte as char).to_string(), false), 1),
+            [] => break,
+        };
+
+        keys.extend(button.map(Key::Button));
+        bytes = &bytes[len..];
+    }
+
+    keys
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mark {
+    Selected,
+    Grabbed,
+    Hinted,
+}
+
+// The cards that are marked in each cell, by index, where the index one past the last card is the
+// space below it.
+struct Marks(Vec<(u8, Range<usize>, Mark)>);
+
+impl Marks {
+    fn new(state: &GameState) -> Marks {
+        let layout = state.ruleset.layout;
+        let cards = |pos: u8, depth: u8| {
+            let len = state.cells[pos as usize].len();
+            let start = len.saturating_sub(depth as usize + 1);
+
+            start..len.max(start + 1)
+        };
+        let space = |pos: u8| {
+            let len = state.cells[pos as usize].len();
+
+            if layout.is_tableau(pos) && len > 0 {
+                len..len + 1
+            } else {
+                0..1
+            }
+        };
+        let button = |index: u8| index as usize..index as usize + 1;
+
+        let mut marks = Vec::new();
+
+        if state.selectpos == layout.button_column() {
+            marks.push((state.selectpos, button(state.selectdepth), Mark::Selected));
+        } else if state.selectdrop {
+            marks.push((state.selectpos, space(state.selectpos), Mark::Selected));
+        } else {
+            let range = cards(state.selectpos, state.selectdepth);
+            marks.push((state.selectpos, range, Mark::Selected));
+        }
+
+        if state.selectdrop {
+            let range = cards(state.grabpos, state.grabdepth);
+            marks.push((state.grabpos, range, Mark::Grabbed));
+        }
+
+        match state.hint {
+            Some(Move::Cards {
+                grabpos,
+                depth,
+                droppos,
+            }) => {
+                marks.push((grabpos, cards(grabpos, depth), Mark::Hinted));
+                marks.push((droppos, space(droppos), Mark::Hinted));
+            }
+            Some(Move::Dragons { suit }) => {
+                marks.push((layout.button_column(), button(suit), Mark::Hinted));
+            }
+            None => {}
+        }
+
+        Marks(marks)
+    }
+
+    // The first mark wins, so the selection is drawn over the others.
+    fn at(&self, pos: u8, index: usize) -> Option<Mark> {
+        self.0
+            .iter()
+            .find(|&&(marked_pos, ref range, _)| marked_pos == pos && range.contains(&index))
+            .map(|&(_, _, mark)| mark)
+    }
+
+    fn anywhere_in(&self, pos: u8) -> Option<Mark> {
+        self.0
+            .iter()
+            .find(|&&(marked_pos, _, _)| marked_pos == pos)
+            .map(|&(_, _, mark)| mark)
+    }
+}
+
+// Four characters: the two of `text`, between brackets if it is marked.
+fn slot(text: &str, style: &str, mark: Option<Mark>, colour: bool) -> String {
+    let (left, right, mark_style) = match mark {
+        Some(Mark::Selected) => ('[', ']', "\x1b[1;33m"),
+        Some(Mark::Grabbed) => ('(', ')', "\x1b[1;36m"),
+        Some(Mark::Hinted) => ('{', '}', "\x1b[1;35m"),
+        None => (' ', ' ', ""),
+    };
+
+    if colour {
+        format!(
+            "{}{}\x1b[0m{}{}\x1b[0m{}{}\x1b[0m",
+            mark_style, left, style, text, mark_style, right
+        )
+    } else {
+        format!("{}{}{}", left, text, right)
+    }
+}
+
+fn card_style(card: u8) -> &'static str {
+    if card == CARD_BACK {
+        "\x1b[2m"
+    } else if card == FLOWER_CARD {
+        "\x1b[35m"
+    } else {
+        match getsuit(card) {
+            0 => "\x1b[31m",
+            1 => "\x1b[32m",
+            _ => "\x1b[1m",
+        }
+    }
+}
+
+fn card_slot(card: Option<&u8>, mark: Option<Mark>, colour: bool) -> String {
+    match card {
+        Some(&card) => slot(&card_text(card), card_style(card), mark, colour),
+        None => slot("..", "\x1b[2m", mark, colour),
+    }
+}
+
+// The board as text, with ANSI colours if `colour` is set.
+fn render(state: &GameState, colour: bool) -> String {
+    let layout = state.ruleset.layout;
+    let marks = Marks::new(state);
+    let mut lines = Vec::new();
+
+    // The buttons are stacked in the button column like they are on screen, so the top row is as
+    // tall as that.
+    let button_count = state.ruleset.special_buttons.len() as u8;
+    for row in 0..button_count.max(1) {
+        let mut line = String::new();
+
+        for pos in 0..layout.start_of_tableau() {
+            if pos == layout.button_column() {
+                // A ruleset can have no buttons at all.
+                let index = match button_count.checked_sub(row + 1) {
+                    Some(index) => index,
+                    None => {
+                        line.push_str("    ");
+                        continue;
+                    }
+                };
+                let enabled = state.enabled_buttons.get(index as usize).cloned();
+                let text = if enabled.unwrap_or(false) {
+                    let letter = text::SUIT_LETTERS.get(index as usize).unwrap_or(&'?');
+                    format!("{}D", letter)
+                } else {
+                    "--".to_owned()
+                };
+
+                line.push_str(&slot(&text, "", marks.at(pos, index as usize), colour));
+            } else if row == 0 {
+                let top = state.cells[pos as usize].last();
+                line.push_str(&card_slot(top, marks.anywhere_in(pos), colour));
+            } else {
+                line.push_str("    ");
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines.push(String::new());
+
+    let tableau = layout.start_of_tableau()..=layout.cells_max_index();
+    let rows = tableau
+        .clone()
+        .map(|pos| state.cells[pos as usize].len() + 1)
+        .max()
+        .unwrap_or(1);
+    for row in 0..rows {
+        let mut line = String::new();
+
+        for pos in tableau.clone() {
+            let cell = &state.cells[pos as usize];
+            let mark = marks.at(pos, row);
+
+            if row < cell.len() || (row == 0 && cell.is_empty()) {
+                line.push_str(&card_slot(cell.get(row), mark, colour));
+            } else if mark.is_some() {
+                line.push_str(&slot("  ", "", mark, colour));
+            } else {
+                line.push_str("    ");
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines.push(format!("wins: {}", state.wins));
+    if state.win_done {
+        lines.push("You won! Press Enter to deal another game.".to_owned());
+    }
+    match state.hint {
+        Some(Move::Cards {
+            grabpos,
+            depth,
+            droppos,
+        }) => lines.push(format!(
+            "hint: move {} card(s) from {} to {}",
+            depth + 1,
+            cell_name(layout, grabpos),
+            cell_name(layout, droppos)
+        )),
+        Some(Move::Dragons { suit }) => {
+            let letter = text::SUIT_LETTERS.get(suit as usize).unwrap_or(&'?');
+            lines.push(format!("hint: collect the {}D dragons", letter))
+        }
+        None => {}
+    }
+    lines.push(String::new());
+    lines.push(HELP.to_owned());
+
+    let mut screen = String::new();
+    for line in lines {
+        screen.push_str(line.trim_end());
+        screen.push('\n');
+    }
+
+    screen
+}
+
+fn run<W: Write>(state: &mut GameState, out: &mut W) -> io::Result<()> {
+    let mut stdin = io::stdin();
+    let mut buffer = [0; 64];
+    let mut pressed = VecDeque::new();
+    let mut input = Input::new();
+    let mut last_screen = String::new();
+
+    loop {
+        let start = Instant::now();
+
+        let count = stdin.read(&mut buffer)?;
+        for key in keys(&buffer[..count]) {
+            match key {
+                Key::Quit => return Ok(()),
+                Key::Hint => state.request_hint(),
+                Key::Button(button) => pressed.push_back(button),
+            }
+        }
+
+        // Terminals only say when a key is pressed, so each press holds its button down for a
+        // frame and lets go of it for the next. Presses wait until the game would see them, which
+        // is on the frame the move timer runs out.
+        input.previous_gamepad = input.gamepad;
+        input.gamepad = Button::Ty::empty();
+        if input.previous_gamepad.is_empty() && state.movetimer <= 1 {
+            if let Some(button) = pressed.pop_front() {
+                input.gamepad = button;
+            }
+        }
+
+        state.update(input);
+
+        let screen = render(state, true);
+        if screen != last_screen {
+            write!(out, "\x1b[H\x1b[2J{}", screen)?;
+            out.flush()?;
+            last_screen = screen;
+        }
+
+        if let Some(rest) = FRAME.checked_sub(start.elapsed()) {
+            thread::sleep(rest);
+        }
+    }
+}
+
+fn play(state: &mut GameState) -> io::Result<()> {
+    let _raw_mode = RawMode::enable()?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    // The alternate screen keeps the game out of the scrollback.
+    write!(stdout, "\x1b[?1049h\x1b[?25l")?;
+    let result = run(state, &mut stdout);
+    write!(stdout, "\x1b[?25h\x1b[?1049l")?;
+    stdout.flush()?;
+
+    result
+}
+
+fn new_game<I: Iterator<Item = String>>(mut args: I) -> Result<GameState, String> {
+    match (args.next(), args.next(), args.next()) {
+        (None, _, _) => {
+            let since_the_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_else(|_| Duration::new(42, 42));
+
+            let mut seed = [0; 16];
+            seed[..8].copy_from_slice(&since_the_epoch.as_secs().to_le_bytes());
+            seed[8..12].copy_from_slice(&since_the_epoch.subsec_nanos().to_le_bytes());
+
+            Ok(GameState::new(seed, None))
+        }
+        (Some(ref flag), Some(ref deal), None) if flag == "--deal" => deal
+            .parse()
+            .ok()
+            .and_then(|deal| GameState::from_catalogue(deal, None))
+            .ok_or_else(|| format!("there is no deal `{}` in the catalogue", deal)),
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+fn main() {
+    let result = new_game(env::args().skip(1))
+        .and_then(|mut state| play(&mut state).map_err(|error| error.to_string()));
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn keys_map_to_the_same_buttons_as_in_the_browser() {
+        assert_eq!(
+            keys(b"z;xq\x1b[A\x1b[D\x1bOB\r\x7f"),
+            vec![
+                Key::Button(Button::A),
+                Key::Button(Button::A),
+                Key::Button(Button::B),
+                Key::Button(Button::B),
+                Key::Button(Button::Up),
+                Key::Button(Button::Left),
+                Key::Button(Button::Down),
+                Key::Button(Button::Start),
+                Key::Button(Button::Select),
+            ]
+        );
+        assert_eq!(keys(b"a\x1b[Z1h\x03"), vec![Key::Hint, Key::Quit]);
+    }
+
+    #[test]
+    fn a_ruleset_without_buttons_leaves_the_button_column_empty() {
+        let mut ruleset = Ruleset::default();
+        ruleset.special_buttons.clear();
+        let state = GameState::with_ruleset([0; 16], None, Arc::new(ruleset));
+
+        let top_row = render(&state, false).lines().next().unwrap().to_owned();
+        assert_eq!(&top_row[12..16], "    ");
+    }
+
+    #[test]
+    fn the_selection_and_grabbed_cards_are_marked() {
+        let mut state = GameState::new([0; 16], None);
+        for cell in state.cells.iter_mut() {
+            cell.clear();
+        }
+        state.cells[0] = vec![CARD_BACK];
+        state.cells[4] = vec![FLOWER_CARD];
+        state.cells[5] = vec![1];
+        state.cells[8] = vec![9, 18, 7];
+        state.cells[9] = vec![FIRST_GREEN_CARD];
+        state.update_enabled_buttons();
+
+        state.selectpos = 8;
+        state.selectdepth = 1;
+        assert_eq!(
+            render(&state, false),
+            [
+                " ##  ..  ..  --  FL  R1  ..  ..",
+                "             --",
+                "             --",
+                "",
+                " R9  GD  ..  ..  ..  ..  ..  ..",
+                "[G8]",
+                "[R7]",
+                "",
+                "wins: 0",
+                "",
+                HELP,
+                "",
+            ]
+            .join("\n")
+        );
+
+        state.selectdrop = true;
+        state.grabpos = 8;
+        state.grabdepth = 0;
+        state.selectpos = 9;
+        state.hint = Some(Move::Cards {
+            grabpos: 8,
+            depth: 0,
+            droppos: 2,
+        });
+        let screen = render(&state, false);
+        let lines: Vec<&str> = screen.lines().collect();
+        assert_eq!(lines[0], " ##  .. {..} --  FL  R1  ..  ..");
+        assert_eq!(lines[4], " R9  GD  ..  ..  ..  ..  ..  ..");
+        assert_eq!(lines[5], " G8 [  ]");
+        assert_eq!(lines[6], "(R7)");
+        assert_eq!(
+            lines[9],
+            "hint: move 1 card(s) from column 0 to free cell 2"
+        );
+    }
+}